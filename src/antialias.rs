@@ -0,0 +1,617 @@
+#![allow(dead_code)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::random::Pcg32;
+use crate::render::Renderer;
+
+/// Where the samples of one pixel land, as offsets in `0.0..1.0` across
+/// the filter's footprint.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Pattern {
+    /// Cell centers of a k×k grid.
+    Grid,
+    /// One random point in each cell of a k×k grid.
+    Jittered,
+    /// The Halton sequence in bases 2 and 3.
+    Halton,
+    /// The first two dimensions of the Sobol sequence.
+    Sobol,
+}
+
+/// Reconstruction filters, weighting each sample by its distance from the
+/// pixel center.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Filter {
+    /// Every sample inside the pixel counts equally.
+    Box,
+    /// Linear falloff over one pixel in each direction.
+    Tent,
+    /// A Gaussian with σ = 0.5 pixels, truncated at 1.5 pixels.
+    Gaussian,
+    /// Mitchell-Netravali with B = C = 1/3 over two pixels. Its negative
+    /// lobes sharpen edges slightly.
+    Mitchell,
+}
+
+const GAUSSIAN_SIGMA: f64 = 0.5;
+const MITCHELL_B: f64 = 1.0 / 3.0;
+const MITCHELL_C: f64 = 1.0 / 3.0;
+
+impl Filter {
+    /// How far from the pixel center, in pixels, samples are drawn.
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// The weight of a sample `(dx, dy)` pixels from the center.
+    pub fn weight(&self, dx: f64, dy: f64) -> f64 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(&self, d: f64) -> f64 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.0;
+        }
+
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d,
+            Filter::Gaussian => {
+                let gaussian = |x: f64| (-x * x / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                // shifted so the weight reaches zero at the radius
+                gaussian(d) - gaussian(self.radius())
+            }
+            Filter::Mitchell => {
+                let (b, c) = (MITCHELL_B, MITCHELL_C);
+                let (d2, d3) = (d * d, d * d * d);
+
+                if d < 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * d3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * d2
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                } else {
+                    ((-b - 6.0 * c) * d3
+                        + (6.0 * b + 30.0 * c) * d2
+                        + (-12.0 * b - 48.0 * c) * d
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                }
+            }
+        }
+    }
+}
+
+/// The radical inverse of `i` in `base`: its digits mirrored about the
+/// decimal point.
+fn radical_inverse(mut i: u32, base: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut scale = inverse_base;
+    let mut result = 0.0;
+
+    while i > 0 {
+        result += (i % base) as f64 * scale;
+        i /= base;
+        scale *= inverse_base;
+    }
+
+    result
+}
+
+/// The `i`th point of the two-dimensional Sobol sequence. The first
+/// dimension is the base-2 radical inverse; the second uses the direction
+/// numbers of the polynomial x + 1.
+fn sobol(i: u32) -> (f64, f64) {
+    let mut direction = 1u32 << 31;
+    let (mut x, mut y) = (0u32, 0u32);
+
+    for bit in 0..32 {
+        if i >> bit & 1 == 1 {
+            x ^= 1 << (31 - bit);
+            y ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+
+    let scale = 1.0 / (1u64 << 32) as f64;
+    (x as f64 * scale, y as f64 * scale)
+}
+
+/// Supersampling settings: how many samples each pixel takes, where they
+/// go and how they are weighted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Supersampler {
+    pub samples: usize,
+    pub pattern: Pattern,
+    pub filter: Filter,
+    /// Seeds the jitter and the per-pixel offsets into the sequences.
+    pub seed: u64,
+}
+
+impl Supersampler {
+    /// `samples` is at least 1. `Grid` and `Jittered` round it up to the
+    /// next square number.
+    pub fn new(samples: usize, pattern: Pattern, filter: Filter) -> Self {
+        let samples = samples.max(1);
+        let samples = match pattern {
+            Pattern::Grid | Pattern::Jittered => {
+                let side = (samples as f64).sqrt().ceil() as usize;
+                side * side
+            }
+            Pattern::Halton | Pattern::Sobol => samples,
+        };
+
+        Self {
+            samples,
+            pattern,
+            filter,
+            seed: 0,
+        }
+    }
+
+    /// The sample positions for pixel `(x, y)` as offsets in `0.0..1.0`.
+    pub fn offsets(&self, x: usize, y: usize) -> Vec<(f64, f64)> {
+        // `samples` is public, so it may not have been rounded by `new`
+        let samples = self.samples.max(1);
+        let side = (samples as f64).sqrt().ceil() as usize;
+        let count = match self.pattern {
+            Pattern::Grid | Pattern::Jittered => side * side,
+            Pattern::Halton | Pattern::Sobol => samples,
+        };
+        // Cranley-Patterson rotation, so neighbouring pixels don't share
+        // the exact same sample positions
+        let mut rng = Pcg32::for_pixel(self.seed, x, y);
+        let (rotate_x, rotate_y) = rng.next_2d();
+
+        (0..count)
+            .map(|i| match self.pattern {
+                Pattern::Grid => (
+                    ((i % side) as f64 + 0.5) / side as f64,
+                    ((i / side) as f64 + 0.5) / side as f64,
+                ),
+                Pattern::Jittered => {
+                    let (u, v) = rng.next_2d();
+                    (
+                        ((i % side) as f64 + u) / side as f64,
+                        ((i / side) as f64 + v) / side as f64,
+                    )
+                }
+                Pattern::Halton => (
+                    (radical_inverse(i as u32, 2) + rotate_x).fract(),
+                    (radical_inverse(i as u32, 3) + rotate_y).fract(),
+                ),
+                Pattern::Sobol => {
+                    let (u, v) = sobol(i as u32);
+                    ((u + rotate_x).fract(), (v + rotate_y).fract())
+                }
+            })
+            .collect()
+    }
+
+    /// Shades pixel `(x, y)`: every sample offset is mapped across the
+    /// filter's footprint around the pixel center and the weighted colors
+    /// are averaged.
+    pub fn shade_pixel<F>(&self, x: usize, y: usize, shade: &F) -> Color
+    where
+        F: Fn(f64, f64) -> Color,
+    {
+        let radius = self.filter.radius();
+        let (center_x, center_y) = (x as f64 + 0.5, y as f64 + 0.5);
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        let mut total_weight = 0.0;
+
+        for (u, v) in self.offsets(x, y) {
+            let (dx, dy) = ((2.0 * u - 1.0) * radius, (2.0 * v - 1.0) * radius);
+            let weight = self.filter.weight(dx, dy);
+            if weight == 0.0 {
+                continue;
+            }
+
+            let color = shade(center_x + dx, center_y + dy);
+            sum = sum
+                + Color::new(
+                    color.red * weight,
+                    color.green * weight,
+                    color.blue * weight,
+                );
+            total_weight += weight;
+        }
+
+        if total_weight == 0.0 {
+            return shade(center_x, center_y);
+        }
+        Color::new(
+            sum.red / total_weight,
+            sum.green / total_weight,
+            sum.blue / total_weight,
+        )
+    }
+}
+
+impl Renderer {
+    /// Renders with several samples per pixel. `shade` takes continuous
+    /// film coordinates, where pixel `(x, y)` covers `x..x + 1` and
+    /// `y..y + 1`, so a single centered sample is at `(x + 0.5, y + 0.5)`.
+    pub fn render_supersampled<F>(
+        &self,
+        width: usize,
+        height: usize,
+        sampler: &Supersampler,
+        shade: F,
+    ) -> Canvas
+    where
+        F: Fn(f64, f64) -> Color + Sync,
+    {
+        self.render(width, height, |x, y| sampler.shade_pixel(x, y, &shade))
+    }
+}
+
+/// The largest per-channel difference between two colors.
+fn contrast(a: Color, b: Color) -> f64 {
+    let difference = a - b;
+    difference
+        .red
+        .abs()
+        .max(difference.green.abs())
+        .max(difference.blue.abs())
+}
+
+fn average(colors: &[Color]) -> Color {
+    let sum = colors
+        .iter()
+        .fold(Color::new(0.0, 0.0, 0.0), |sum, &color| sum + color);
+    let n = colors.len() as f64;
+
+    Color::new(sum.red / n, sum.green / n, sum.blue / n)
+}
+
+/// Adaptive antialiasing settings. Pixels whose color differs from a
+/// neighbour's by more than `threshold` in any channel are split into
+/// quadrants, and quadrants that still disagree are split again, up to
+/// `max_depth` levels.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Adaptive {
+    pub threshold: f64,
+    pub max_depth: u32,
+}
+
+impl Adaptive {
+    pub fn new(threshold: f64, max_depth: u32) -> Self {
+        Self {
+            threshold,
+            max_depth,
+        }
+    }
+
+    /// Samples the square of side `size` at `(x, y)` at its quadrant
+    /// centers, refining the quadrants that disagree with another. Returns
+    /// the average color and the number of samples taken.
+    fn refine<F>(&self, x: f64, y: f64, size: f64, depth: u32, shade: &F) -> (Color, usize)
+    where
+        F: Fn(f64, f64) -> Color,
+    {
+        let half = size / 2.0;
+        let corners = [(x, y), (x + half, y), (x, y + half), (x + half, y + half)];
+        let colors = corners.map(|(qx, qy)| shade(qx + half / 2.0, qy + half / 2.0));
+        let mut samples = colors.len();
+        let mut refined = colors;
+
+        if depth < self.max_depth {
+            for (i, &(qx, qy)) in corners.iter().enumerate() {
+                let disagrees = colors
+                    .iter()
+                    .any(|&other| contrast(colors[i], other) > self.threshold);
+
+                if disagrees {
+                    let (color, n) = self.refine(qx, qy, half, depth + 1, shade);
+                    refined[i] = color;
+                    samples += n;
+                }
+            }
+        }
+
+        (average(&refined), samples)
+    }
+}
+
+/// The result of an adaptive render: the image and the number of samples
+/// each pixel took, row by row.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AdaptiveRender {
+    pub image: Canvas,
+    pub samples: Vec<usize>,
+}
+
+impl AdaptiveRender {
+    pub fn samples_at(&self, x: usize, y: usize) -> usize {
+        self.samples[y * self.image.width + x]
+    }
+
+    pub fn total_samples(&self) -> usize {
+        self.samples.iter().sum()
+    }
+
+    /// A debug view of where the samples went: each pixel's gray level is
+    /// its sample count relative to the busiest pixel.
+    pub fn sample_map(&self) -> Canvas {
+        let most = self.samples.iter().copied().max().unwrap_or(1) as f64;
+        let mut map = Canvas::new(self.image.width, self.image.height);
+
+        for (color, &n) in map.data.iter_mut().zip(&self.samples) {
+            let level = n as f64 / most;
+            *color = Color::new(level, level, level);
+        }
+
+        map
+    }
+}
+
+impl Renderer {
+    /// Renders one centered sample per pixel, then refines only the pixels
+    /// that contrast with one of their four neighbours. `shade` takes film
+    /// coordinates as in `render_supersampled`.
+    pub fn render_adaptive<F>(
+        &self,
+        width: usize,
+        height: usize,
+        adaptive: &Adaptive,
+        shade: F,
+    ) -> AdaptiveRender
+    where
+        F: Fn(f64, f64) -> Color + Sync,
+    {
+        let centers = self.render(width, height, |x, y| shade(x as f64 + 0.5, y as f64 + 0.5));
+        let samples: Vec<AtomicUsize> = (0..width * height).map(|_| AtomicUsize::new(1)).collect();
+
+        let image = self.render(width, height, |x, y| {
+            let center = centers.pixel_at(x, y);
+            let neighbours = [
+                (x.wrapping_sub(1), y),
+                (x + 1, y),
+                (x, y.wrapping_sub(1)),
+                (x, y + 1),
+            ];
+            let edge = neighbours.iter().any(|&(nx, ny)| {
+                centers
+                    .get_pixel(nx, ny)
+                    .is_some_and(|neighbour| contrast(center, neighbour) > adaptive.threshold)
+            });
+
+            if !edge || adaptive.max_depth == 0 {
+                return center;
+            }
+
+            let (color, n) = adaptive.refine(x as f64, y as f64, 1.0, 1, &shade);
+            samples[y * width + x].fetch_add(n, Ordering::Relaxed);
+            color
+        });
+
+        AdaptiveRender {
+            image,
+            samples: samples.into_iter().map(AtomicUsize::into_inner).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    /// White left of the diagonal line x = y, black right of it.
+    fn diagonal(x: f64, y: f64) -> Color {
+        if x < y {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+
+    #[test]
+    fn generating_low_discrepancy_sequences() {
+        let halton: Vec<f64> = (0..4).map(|i| radical_inverse(i, 3)).collect();
+        assert_eq!(halton[..3], [0.0, 1.0 / 3.0, 2.0 / 3.0]);
+        assert!(equal(halton[3], 1.0 / 9.0));
+
+        let sobol: Vec<(f64, f64)> = (0..8).map(sobol).collect();
+        assert_eq!(
+            sobol,
+            vec![
+                (0.0, 0.0),
+                (0.5, 0.5),
+                (0.25, 0.75),
+                (0.75, 0.25),
+                (0.125, 0.625),
+                (0.625, 0.125),
+                (0.375, 0.375),
+                (0.875, 0.875),
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_stay_inside_the_pixel() {
+        for pattern in [
+            Pattern::Grid,
+            Pattern::Jittered,
+            Pattern::Halton,
+            Pattern::Sobol,
+        ] {
+            let sampler = Supersampler::new(10, pattern, Filter::Box);
+            let offsets = sampler.offsets(3, 7);
+
+            assert_eq!(offsets.len(), sampler.samples);
+            assert!(offsets
+                .iter()
+                .all(|&(u, v)| (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)));
+        }
+
+        assert_eq!(
+            Supersampler::new(10, Pattern::Grid, Filter::Box).samples,
+            16
+        );
+        assert_eq!(
+            Supersampler::new(10, Pattern::Sobol, Filter::Box).samples,
+            10
+        );
+    }
+
+    #[test]
+    fn rounding_samples_set_after_construction() {
+        for pattern in [Pattern::Grid, Pattern::Jittered] {
+            let mut sampler = Supersampler::new(4, pattern, Filter::Box);
+            sampler.samples = 5;
+            let offsets = sampler.offsets(2, 1);
+
+            assert_eq!(offsets.len(), 9);
+            assert!(offsets
+                .iter()
+                .all(|&(u, v)| (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)));
+        }
+
+        let mut sampler = Supersampler::new(4, Pattern::Halton, Filter::Box);
+        sampler.samples = 0;
+        assert_eq!(sampler.offsets(0, 0).len(), 1);
+    }
+
+    #[test]
+    fn stratifying_jittered_samples() {
+        let sampler = Supersampler::new(4, Pattern::Jittered, Filter::Box);
+        let offsets = sampler.offsets(0, 0);
+
+        // one sample in each quadrant
+        for (i, (u, v)) in offsets.into_iter().enumerate() {
+            assert_eq!(((u * 2.0) as usize, (v * 2.0) as usize), (i % 2, i / 2));
+        }
+        assert_ne!(sampler.offsets(0, 0), sampler.offsets(1, 0));
+    }
+
+    #[test]
+    fn filters_peak_at_the_center() {
+        for filter in [
+            Filter::Box,
+            Filter::Tent,
+            Filter::Gaussian,
+            Filter::Mitchell,
+        ] {
+            let center = filter.weight(0.0, 0.0);
+
+            assert!(center > 0.0);
+            assert_eq!(filter.weight(filter.radius() + 0.01, 0.0), 0.0);
+            assert!(filter.weight(0.3, 0.2) <= center);
+        }
+
+        assert!(equal(Filter::Tent.weight(0.5, 0.5), 0.25));
+        assert!(equal(
+            Filter::Mitchell.weight(0.0, 0.0),
+            (8.0 / 9.0) * (8.0 / 9.0)
+        ));
+        assert!(Filter::Mitchell.weight(1.5, 0.0) < 0.0);
+    }
+
+    #[test]
+    fn antialiasing_an_edge() {
+        let renderer = Renderer::new(2, 4);
+        let aliased = renderer.render(4, 4, |x, y| diagonal(x as f64 + 0.5, y as f64 + 0.5));
+        let sampler = Supersampler::new(64, Pattern::Grid, Filter::Box);
+        let smooth = renderer.render_supersampled(4, 4, &sampler, diagonal);
+
+        // pixel centers on the diagonal fall on the black side
+        assert_eq!(aliased.pixel_at(1, 1), Color::new(0.0, 0.0, 0.0));
+        // with 8x8 samples, 28 of 64 lie strictly above the diagonal
+        assert!(equal(smooth.pixel_at(1, 1).red, 28.0 / 64.0));
+        assert_eq!(smooth.pixel_at(0, 3), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(smooth.pixel_at(3, 0), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn every_pattern_and_filter_converges_on_the_edge() {
+        for pattern in [Pattern::Jittered, Pattern::Halton, Pattern::Sobol] {
+            for filter in [
+                Filter::Box,
+                Filter::Tent,
+                Filter::Gaussian,
+                Filter::Mitchell,
+            ] {
+                let sampler = Supersampler::new(1024, pattern, filter);
+                let color = sampler.shade_pixel(5, 5, &diagonal);
+
+                // the footprint is symmetric about the diagonal
+                assert!(
+                    (color.red - 0.5).abs() < 0.05,
+                    "{:?} {:?}: {}",
+                    pattern,
+                    filter,
+                    color.red
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn supersampled_renders_are_deterministic() {
+        let sampler = Supersampler::new(16, Pattern::Jittered, Filter::Gaussian);
+        let serial = Renderer::new(1, 16).render_supersampled(9, 5, &sampler, diagonal);
+        let parallel = Renderer::new(4, 2).render_supersampled(9, 5, &sampler, diagonal);
+
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn flat_regions_take_one_sample() {
+        let gray = |_: f64, _: f64| Color::new(0.5, 0.5, 0.5);
+        let result = Renderer::new(2, 4).render_adaptive(6, 4, &Adaptive::new(0.05, 3), gray);
+
+        assert_eq!(result.samples, vec![1; 24]);
+        assert!(result
+            .image
+            .pixels()
+            .all(|&c| c == Color::new(0.5, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn refining_only_near_an_edge() {
+        let adaptive = Adaptive::new(0.05, 3);
+        let result = Renderer::new(2, 4).render_adaptive(8, 8, &adaptive, diagonal);
+
+        // far from the diagonal nothing changes
+        assert_eq!(result.samples_at(7, 0), 1);
+        assert_eq!(result.samples_at(0, 7), 1);
+        assert_eq!(result.image.pixel_at(0, 7), Color::new(1.0, 1.0, 1.0));
+
+        // on the diagonal, roughly half the pixel is covered
+        let on_edge = result.image.pixel_at(4, 4).red;
+        assert!(result.samples_at(4, 4) > 4);
+        assert!((on_edge - 0.5).abs() < 0.1, "{}", on_edge);
+
+        assert!(result.total_samples() < 64 * 64);
+    }
+
+    #[test]
+    fn limiting_the_refinement_depth() {
+        let shallow = Renderer::new(1, 8).render_adaptive(4, 4, &Adaptive::new(0.05, 1), diagonal);
+        let none = Renderer::new(1, 8).render_adaptive(4, 4, &Adaptive::new(0.05, 0), diagonal);
+
+        assert!(shallow.samples.iter().all(|&n| n == 1 || n == 5));
+        assert_eq!(none.samples, vec![1; 16]);
+    }
+
+    #[test]
+    fn mapping_sample_counts() {
+        let result = Renderer::new(1, 8).render_adaptive(4, 4, &Adaptive::new(0.05, 2), diagonal);
+        let map = result.sample_map();
+        let busiest = result.samples.iter().copied().max().unwrap();
+
+        assert!(map.pixels().any(|&c| c == Color::new(1.0, 1.0, 1.0)));
+        let level = 1.0 / busiest as f64;
+        assert_eq!(map.pixel_at(3, 0), Color::new(level, level, level));
+    }
+}
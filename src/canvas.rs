@@ -1,89 +1,243 @@
 #![allow(dead_code, unused_variables)]
+use std::error::Error;
+use std::fmt;
+use std::slice::{self, ChunksExact, ChunksExactMut};
+
 use crate::color::Color;
 
+pub mod bmp;
+pub mod composite;
+mod deflate;
+pub mod exr;
+pub mod file;
+pub mod hdr;
+pub mod pfm;
+pub mod png;
+pub mod ppm;
+pub mod tga;
+
+use ppm::PpmFormat;
+
+/// Drops an alpha plane read from a file when every pixel is opaque, so
+/// that images without real transparency compare equal to plain canvases.
+fn alpha_plane(alpha: Vec<f64>) -> Option<Vec<f64>> {
+    if alpha.iter().all(|&a| a >= 1.0) {
+        None
+    } else {
+        Some(alpha)
+    }
+}
+
+/// Clamps a channel to `0.0..=1.0` and rounds it to an integer sample in
+/// `0..=max`, as used by the fixed-point image formats.
+fn quantize(value: f64, max: f64) -> u16 {
+    (value.clamp(0.0, 1.0) * max).round() as u16
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Canvas {
     pub width: usize,
     pub height: usize,
-    pub data: Vec<Vec<Color>>,
+    /// Pixels in row-major order: `(x, y)` lives at `y * width + x`.
+    pub data: Vec<Color>,
+    /// Straight (not premultiplied) coverage for each pixel, in the same
+    /// order as `data`. `None` means the canvas is fully opaque.
+    pub alpha: Option<Vec<f64>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OutOfBounds {
+    pub x: usize,
+    pub y: usize,
 }
 
+impl fmt::Display for OutOfBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pixel ({}, {}) is outside the canvas", self.x, self.y)
+    }
+}
+
+impl Error for OutOfBounds {}
+
 impl Canvas {
     pub fn new(width: usize, height: usize) -> Self {
-        let data = vec![vec![Color::new(0.0, 0.0, 0.0); width]; height];
+        let data = vec![Color::new(0.0, 0.0, 0.0); width * height];
         Self {
             width,
             height,
             data,
+            alpha: None,
         }
     }
 
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    /// Renders the whole canvas as an ASCII P3 file; see `write_ppm` to
+    /// stream it instead. Values are written as-is, without a transfer
+    /// function; `save` encodes to sRGB first.
     pub fn to_ppm(&self) -> String {
-        let mut ppm: String = String::new();
-
-        // ppm header
-        ppm.push_str("P3\n");
-        ppm.push_str(&format!("{} {}\n", self.width, self.height));
-        ppm.push_str("255\n");
-
-        // pixel data
-        for (row_index, row) in self.data.iter().enumerate() {
-            let mut line = String::new();
-
-            for color in row.clone() {
-                let scaled = color * 255;
-                let red = scaled.red.ceil().clamp(0.0, 255.0);
-                let pixel = format!("{} ", red);
-                if line.chars().count() + pixel.chars().count() > 70 {
-                    ppm.push_str(&line.trim_end());
-                    ppm.push('\n');
-                    line.clear();
-                }
-                line.push_str(&pixel);
-
-                let green = scaled.green.ceil().clamp(0.0, 255.0);
-                let pixel = format!("{} ", green);
-                if line.chars().count() + pixel.chars().count() > 70 {
-                    ppm.push_str(&line.trim_end());
-                    ppm.push('\n');
-                    line.clear();
-                }
-                line.push_str(&pixel);
-
-                let blue = scaled.blue.ceil().clamp(0.0, 255.0);
-                let pixel = format!("{} ", blue);
-                if line.chars().count() + pixel.chars().count() > 70 {
-                    ppm.push_str(&line.trim_end());
-                    ppm.push('\n');
-                    line.clear();
-                }
-                line.push_str(&pixel);
-            }
-
-            if !line.is_empty() {
-                ppm.push_str(&line.trim_end());
-                ppm.push('\n')
-            }
+        let mut ppm = vec![];
+        self.write_ppm(&mut ppm, PpmFormat::Ascii, 255)
+            .expect("writing to a Vec can't fail");
+
+        String::from_utf8(ppm).expect("P3 output is ASCII")
+    }
+
+    /// Panics if `(x, y)` is outside the canvas; see `get_pixel`.
+    pub fn pixel_at(&self, x: usize, y: usize) -> Color {
+        match self.get_pixel(x, y) {
+            Some(color) => color,
+            None => panic!("{}", OutOfBounds { x, y }),
         }
+    }
 
-        ppm
+    pub fn get_pixel(&self, x: usize, y: usize) -> Option<Color> {
+        self.index(x, y).map(|i| self.data[i])
+    }
+
+    /// Panics if `(x, y)` is outside the canvas.
+    pub fn alpha_at(&self, x: usize, y: usize) -> f64 {
+        match self.index(x, y) {
+            Some(i) => self.opacity(i),
+            None => panic!("{}", OutOfBounds { x, y }),
+        }
+    }
+
+    /// Sets the coverage of one pixel, giving the canvas an alpha plane if
+    /// it didn't have one. Panics if `(x, y)` is outside the canvas.
+    pub fn write_alpha(&mut self, x: usize, y: usize, alpha: f64) {
+        let i = match self.index(x, y) {
+            Some(i) => i,
+            None => panic!("{}", OutOfBounds { x, y }),
+        };
+        let len = self.data.len();
+
+        self.alpha.get_or_insert_with(|| vec![1.0; len])[i] = alpha;
+    }
+
+    /// Alpha of the pixel at row-major index `i`.
+    fn opacity(&self, i: usize) -> f64 {
+        self.alpha.as_ref().map_or(1.0, |alpha| alpha[i])
     }
 
+    /// Panics if `(x, y)` is outside the canvas; see `try_write_pixel`.
     pub fn write_pixel(&mut self, x: usize, y: usize, color: Color) {
-        for (row_index, row) in self.data.iter_mut().enumerate() {
-            for (col_index, element) in row.iter_mut().enumerate() {
-                if y == row_index && x == col_index {
-                    *element = color.clone();
-                }
-            }
+        if let Err(err) = self.try_write_pixel(x, y, color) {
+            panic!("{}", err);
         }
     }
 
-    fn fill_every_with(&mut self, default_color: Color) {
-        for (row_index, row) in self.data.iter_mut().enumerate() {
-            for (col_index, element) in row.iter_mut().enumerate() {
-                *element = default_color.clone();
-            }
+    pub fn try_write_pixel(&mut self, x: usize, y: usize, color: Color) -> Result<(), OutOfBounds> {
+        let i = self.index(x, y).ok_or(OutOfBounds { x, y })?;
+        self.data[i] = color;
+
+        Ok(())
+    }
+
+    pub fn row(&self, y: usize) -> &[Color] {
+        &self.data[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> &mut [Color] {
+        &mut self.data[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn rows(&self) -> ChunksExact<'_, Color> {
+        self.data.chunks_exact(self.width.max(1))
+    }
+
+    pub fn rows_mut(&mut self) -> ChunksExactMut<'_, Color> {
+        self.data.chunks_exact_mut(self.width.max(1))
+    }
+
+    pub fn pixels(&self) -> slice::Iter<'_, Color> {
+        self.data.iter()
+    }
+
+    /// Yields `(x, y, color)` for every pixel in row-major order.
+    pub fn enumerate_pixels(&self) -> impl Iterator<Item = (usize, usize, &Color)> {
+        let width = self.width;
+        self.data
+            .iter()
+            .enumerate()
+            .map(move |(i, color)| (i % width, i / width, color))
+    }
+
+    pub fn enumerate_pixels_mut(&mut self) -> impl Iterator<Item = (usize, usize, &mut Color)> {
+        let width = self.width;
+        self.data
+            .iter_mut()
+            .enumerate()
+            .map(move |(i, color)| (i % width, i / width, color))
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        self.data.fill(color);
+    }
+
+    /// Returns a new canvas with `f` applied to every pixel.
+    pub fn map<F: Fn(Color) -> Color>(&self, f: F) -> Canvas {
+        Canvas {
+            width: self.width,
+            height: self.height,
+            data: self.data.iter().map(|&color| f(color)).collect(),
+            alpha: self.alpha.clone(),
+        }
+    }
+
+    /// Looks up the canvas at texture coordinates `(u, v)`, where `(0, 0)`
+    /// is the top-left corner and `(1, 1)` the bottom-right, blending the
+    /// four nearest pixels. Coordinates outside the canvas clamp to its
+    /// edge, and an empty canvas samples as black.
+    pub fn sample_bilinear(&self, u: f64, v: f64) -> Color {
+        self.bilinear(u, v, false)
+    }
+
+    /// Like `sample_bilinear`, but `u` wraps around so the left and right
+    /// edges meet, as in panoramas.
+    pub fn sample_bilinear_wrapped(&self, u: f64, v: f64) -> Color {
+        self.bilinear(u, v, true)
+    }
+
+    fn bilinear(&self, u: f64, v: f64, wrap: bool) -> Color {
+        if self.width == 0 || self.height == 0 {
+            return Color::new(0.0, 0.0, 0.0);
         }
+
+        let x = u * self.width as f64 - 0.5;
+        let (x0, x1, tx) = if wrap {
+            let x0 = x.floor();
+            let left = (x0 as i64).rem_euclid(self.width as i64) as usize;
+            (left, (left + 1) % self.width, x - x0)
+        } else {
+            let x = x.clamp(0.0, (self.width - 1) as f64);
+            let x0 = x.floor() as usize;
+            (x0, (x0 + 1).min(self.width - 1), x - x0 as f64)
+        };
+        let y = (v * self.height as f64 - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let y0 = y.floor() as usize;
+        let (y1, ty) = ((y0 + 1).min(self.height - 1), y - y0 as f64);
+
+        let lerp = |a: Color, b: Color, t: f64| {
+            Color::new(
+                a.red + (b.red - a.red) * t,
+                a.green + (b.green - a.green) * t,
+                a.blue + (b.blue - a.blue) * t,
+            )
+        };
+        let top = lerp(self.pixel_at(x0, y0), self.pixel_at(x1, y0), tx);
+        let bottom = lerp(self.pixel_at(x0, y1), self.pixel_at(x1, y1), tx);
+        lerp(top, bottom, ty)
+    }
+
+    fn fill_every_with(&mut self, default_color: Color) {
+        self.fill(default_color);
     }
 }
 
@@ -94,11 +248,115 @@ fn creating_canvas() {
     assert_eq!(c.width, 10);
     assert_eq!(c.height, 20);
 
-    for (row_index, row) in c.data.iter().enumerate() {
-        for (col_index, element) in row.iter().enumerate() {
-            assert_eq!(*element, Color::new(0.0, 0.0, 0.0));
-        }
+    assert_eq!(c.data.len(), 200);
+    for element in c.pixels() {
+        assert_eq!(*element, Color::new(0.0, 0.0, 0.0));
+    }
+}
+
+#[test]
+fn mapping_every_pixel() {
+    let mut c = Canvas::new(2, 1);
+    c.write_pixel(1, 0, Color::new(0.5, 1.0, 2.0));
+
+    let doubled = c.map(|color| color * 2);
+
+    assert_eq!(doubled.width, 2);
+    assert_eq!(doubled.pixel_at(0, 0), Color::new(0.0, 0.0, 0.0));
+    assert_eq!(doubled.pixel_at(1, 0), Color::new(1.0, 2.0, 4.0));
+}
+
+#[test]
+fn sampling_between_pixels() {
+    let mut c = Canvas::new(2, 2);
+    c.write_pixel(1, 0, Color::new(1.0, 0.0, 0.0));
+    c.write_pixel(0, 1, Color::new(0.0, 1.0, 0.0));
+    c.write_pixel(1, 1, Color::new(0.0, 0.0, 1.0));
+
+    assert_eq!(c.sample_bilinear(0.25, 0.25), Color::new(0.0, 0.0, 0.0));
+    assert_eq!(c.sample_bilinear(0.5, 0.25), Color::new(0.5, 0.0, 0.0));
+    assert_eq!(c.sample_bilinear(0.5, 0.5), Color::new(0.25, 0.25, 0.25));
+    // outside the pixel centers the edge is held
+    assert_eq!(c.sample_bilinear(1.5, -1.0), Color::new(1.0, 0.0, 0.0));
+
+    // wrapping blends across the left and right edges instead
+    assert_eq!(
+        c.sample_bilinear_wrapped(0.0, 0.25),
+        Color::new(0.5, 0.0, 0.0)
+    );
+    assert_eq!(
+        c.sample_bilinear_wrapped(1.25, 0.25),
+        Color::new(0.0, 0.0, 0.0)
+    );
+}
+
+#[test]
+fn sampling_an_empty_canvas() {
+    let c = Canvas::new(0, 3);
+
+    assert_eq!(c.sample_bilinear(0.5, 0.5), Color::new(0.0, 0.0, 0.0));
+    assert_eq!(
+        c.sample_bilinear_wrapped(0.5, 0.5),
+        Color::new(0.0, 0.0, 0.0)
+    );
+}
+
+#[test]
+fn writing_pixels_to_canvas() {
+    let mut c = Canvas::new(10, 20);
+    let red = Color::new(1.0, 0.0, 0.0);
+
+    c.write_pixel(2, 3, red);
+
+    assert_eq!(c.pixel_at(2, 3), red);
+    assert_eq!(c.data[3 * 10 + 2], red);
+    assert_eq!(c.pixel_at(3, 2), Color::new(0.0, 0.0, 0.0));
+}
+
+#[test]
+fn bounds_checked_pixel_access() {
+    let mut c = Canvas::new(4, 3);
+    let green = Color::new(0.0, 1.0, 0.0);
+
+    assert_eq!(c.get_pixel(3, 2), Some(Color::new(0.0, 0.0, 0.0)));
+    assert_eq!(c.get_pixel(4, 0), None);
+    assert_eq!(c.get_pixel(0, 3), None);
+
+    assert_eq!(c.try_write_pixel(3, 2, green), Ok(()));
+    assert_eq!(
+        c.try_write_pixel(0, 3, green),
+        Err(OutOfBounds { x: 0, y: 3 })
+    );
+    assert_eq!(c.pixel_at(3, 2), green);
+}
+
+#[test]
+#[should_panic(expected = "pixel (4, 0) is outside the canvas")]
+fn writing_outside_the_canvas_panics() {
+    let mut c = Canvas::new(4, 3);
+
+    c.write_pixel(4, 0, Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn rows_and_enumerated_pixels() {
+    let mut c = Canvas::new(3, 2);
+
+    for (y, row) in c.rows_mut().enumerate() {
+        row[1] = Color::new(y as f64, 0.0, 0.0);
+    }
+
+    assert_eq!(c.rows().count(), 2);
+    assert_eq!(c.row(1)[1], Color::new(1.0, 0.0, 0.0));
+
+    for (x, y, color) in c.enumerate_pixels_mut() {
+        color.blue = (y * 3 + x) as f64;
     }
+
+    let coords: Vec<(usize, usize)> = c.enumerate_pixels().map(|(x, y, _)| (x, y)).collect();
+    assert_eq!(coords, vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1), (2, 1)]);
+    assert_eq!(c.pixel_at(2, 1).blue, 5.0);
+    assert_eq!(c.pixel_at(1, 1).red, 1.0);
 }
 
 #[ignore = "no need to create ppm header"]
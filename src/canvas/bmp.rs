@@ -0,0 +1,740 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::{alpha_plane, quantize, Canvas};
+use crate::color::Color;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const V4_HEADER_SIZE: usize = 108;
+const CORE_HEADER_SIZE: usize = 12;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// The most pixels a compressed bitmap may have. Escapes can skip any
+/// part of the image, so its size can't be checked against the data.
+const MAX_RLE_PIXELS: usize = 1 << 28;
+
+/// 72 DPI, in pixels per metre.
+const RESOLUTION: u32 = 2835;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BmpFormat {
+    /// Uncompressed 24-bit BGR.
+    Rgb24,
+    /// Uncompressed 32-bit BGRA with a V4 header describing the channel masks.
+    Rgba32,
+    /// Run-length encoded 8-bit palette. Only possible when the image has
+    /// at most 256 distinct 8-bit colors.
+    Rle8,
+}
+
+#[derive(Debug)]
+pub enum BmpError {
+    Io(io::Error),
+    NotBmp,
+    Truncated,
+    Unsupported(&'static str),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for BmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BmpError::Io(err) => write!(f, "failed to read image: {}", err),
+            BmpError::NotBmp => write!(f, "not a BMP file"),
+            BmpError::Truncated => write!(f, "BMP file is truncated"),
+            BmpError::Unsupported(what) => write!(f, "unsupported BMP feature: {}", what),
+            BmpError::Invalid(reason) => write!(f, "invalid BMP file: {}", reason),
+        }
+    }
+}
+
+impl Error for BmpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BmpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BmpError {
+    fn from(err: io::Error) -> Self {
+        BmpError::Io(err)
+    }
+}
+
+fn to_bgr(color: &Color) -> [u8; 3] {
+    [
+        quantize(color.blue, 255.0) as u8,
+        quantize(color.green, 255.0) as u8,
+        quantize(color.red, 255.0) as u8,
+    ]
+}
+
+/// Encodes one row of palette indices. Repeats become `(count, index)`
+/// pairs; stretches of three or more distinct indices use absolute mode,
+/// `(0, count, indices...)` padded to an even length.
+fn encode_rle8_row(row: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+
+    while i < row.len() {
+        let run = row[i..]
+            .iter()
+            .take(255)
+            .take_while(|&&b| b == row[i])
+            .count();
+
+        if run >= 2 {
+            out.extend([run as u8, row[i]]);
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < row.len() && i - start < 255 {
+            if i + 1 < row.len() && row[i] == row[i + 1] {
+                break;
+            }
+            i += 1;
+        }
+
+        let literals = &row[start..i];
+        if literals.len() < 3 {
+            for &index in literals {
+                out.extend([1, index]);
+            }
+        } else {
+            out.extend([0, literals.len() as u8]);
+            out.extend(literals);
+            if literals.len() % 2 == 1 {
+                out.push(0);
+            }
+        }
+    }
+
+    // end of line
+    out.extend([0, 0]);
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    top_down: bool,
+    bits: u16,
+    compression: u32,
+    masks: [u32; 4],
+    palette: Vec<Color>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Result<u16, BmpError> {
+    let b = bytes.get(at..at + 2).ok_or(BmpError::Truncated)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32, BmpError> {
+    let b = bytes.get(at..at + 4).ok_or(BmpError::Truncated)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Reads the channel selected by `mask` out of a packed pixel as a fraction
+/// of its full range.
+fn extract(pixel: u32, mask: u32) -> f64 {
+    if mask == 0 {
+        return 0.0;
+    }
+    let shift = mask.trailing_zeros();
+    ((pixel & mask) >> shift) as f64 / (mask >> shift) as f64
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Result<Self, BmpError> {
+        let size = u32_at(bytes, FILE_HEADER_SIZE)? as usize;
+        let info = FILE_HEADER_SIZE;
+
+        let (width, height, bits, compression, entry_size) = if size == CORE_HEADER_SIZE {
+            let width = u16_at(bytes, info + 4)? as i64;
+            let height = u16_at(bytes, info + 6)? as i64;
+            (width, height, u16_at(bytes, info + 10)?, BI_RGB, 3)
+        } else if size >= INFO_HEADER_SIZE {
+            let width = u32_at(bytes, info + 4)? as i32 as i64;
+            let height = u32_at(bytes, info + 8)? as i32 as i64;
+            (
+                width,
+                height,
+                u16_at(bytes, info + 14)?,
+                u32_at(bytes, info + 16)?,
+                4,
+            )
+        } else {
+            return Err(BmpError::Unsupported("unknown header size"));
+        };
+
+        if width <= 0 || height == 0 {
+            return Err(BmpError::Invalid("image has no pixels"));
+        }
+
+        let mut masks = match bits {
+            16 => [0x7c00, 0x03e0, 0x001f, 0],
+            24 | 32 => [0xff0000, 0xff00, 0xff, 0],
+            1 | 4 | 8 => [0; 4],
+            _ => return Err(BmpError::Unsupported("bit depth")),
+        };
+
+        // Bit field masks live in the header from V2 on, and straight after
+        // a plain info header otherwise.
+        let mut palette_start = info + size;
+        match compression {
+            BI_RGB => {}
+            BI_RLE8 if bits == 8 => {}
+            BI_RLE4 if bits == 4 => {}
+            BI_BITFIELDS | BI_ALPHABITFIELDS if bits == 16 || bits == 32 => {
+                let count = if compression == BI_ALPHABITFIELDS || size > 52 {
+                    4
+                } else {
+                    3
+                };
+                for (i, mask) in masks.iter_mut().enumerate().take(count) {
+                    *mask = u32_at(bytes, info + 40 + i * 4)?;
+                }
+                if size == INFO_HEADER_SIZE {
+                    palette_start += count * 4;
+                }
+            }
+            _ => return Err(BmpError::Unsupported("compression method")),
+        }
+
+        let mut palette = vec![];
+        if bits <= 8 {
+            let used = if size >= INFO_HEADER_SIZE {
+                u32_at(bytes, info + 32)? as usize
+            } else {
+                0
+            };
+            let count = if used == 0 { 1 << bits } else { used.min(256) };
+            // Some writers leave the count at zero but store a short palette.
+            let offset = u32_at(bytes, 10)? as usize;
+            let count = count.min(offset.saturating_sub(palette_start) / entry_size);
+            for i in 0..count {
+                let at = palette_start + i * entry_size;
+                let bgr = bytes.get(at..at + 3).ok_or(BmpError::Truncated)?;
+                palette.push(Color::new(
+                    bgr[2] as f64 / 255.0,
+                    bgr[1] as f64 / 255.0,
+                    bgr[0] as f64 / 255.0,
+                ));
+            }
+        }
+
+        Ok(Self {
+            width: width as usize,
+            height: height.unsigned_abs() as usize,
+            top_down: height < 0,
+            bits,
+            compression,
+            masks,
+            palette,
+        })
+    }
+
+    fn color(&self, index: usize) -> Result<Color, BmpError> {
+        self.palette
+            .get(index)
+            .copied()
+            .ok_or(BmpError::Invalid("palette index out of range"))
+    }
+
+    /// Converts a packed 16- or 32-bit pixel through the channel masks.
+    fn unpack(&self, pixel: u32) -> Color {
+        Color::new(
+            extract(pixel, self.masks[0]),
+            extract(pixel, self.masks[1]),
+            extract(pixel, self.masks[2]),
+        )
+    }
+
+    /// The alpha of a packed pixel, if the header declares an alpha mask.
+    fn unpack_alpha(&self, pixel: u32) -> Option<f64> {
+        match self.masks[3] {
+            0 => None,
+            mask => Some(extract(pixel, mask)),
+        }
+    }
+}
+
+/// Expands RLE4/RLE8 data into a bottom-up grid of palette indices.
+/// Pixels skipped by delta escapes keep index 0.
+fn decode_rle(
+    data: &[u8],
+    width: usize,
+    height: usize,
+    four_bit: bool,
+) -> Result<Vec<u8>, BmpError> {
+    let mut indices = vec![0u8; width * height];
+    let (mut x, mut y, mut pos) = (0usize, 0usize, 0usize);
+    let mut put = |x: &mut usize, y: usize, index: u8| {
+        if *x < width && y < height {
+            indices[y * width + *x] = index;
+        }
+        *x += 1;
+    };
+
+    loop {
+        let pair = data.get(pos..pos + 2).ok_or(BmpError::Truncated)?;
+        let (count, value) = (pair[0] as usize, pair[1]);
+        pos += 2;
+
+        if count > 0 {
+            for i in 0..count {
+                let index = match (four_bit, i % 2) {
+                    (true, 0) => value >> 4,
+                    (true, _) => value & 0x0f,
+                    _ => value,
+                };
+                put(&mut x, y, index);
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                x = 0;
+                y += 1;
+            }
+            1 => break,
+            2 => {
+                let delta = data.get(pos..pos + 2).ok_or(BmpError::Truncated)?;
+                x += delta[0] as usize;
+                y += delta[1] as usize;
+                pos += 2;
+            }
+            literals => {
+                let literals = literals as usize;
+                let len = if four_bit {
+                    literals.div_ceil(2)
+                } else {
+                    literals
+                };
+                let bytes = data.get(pos..pos + len).ok_or(BmpError::Truncated)?;
+                for i in 0..literals {
+                    let index = if four_bit {
+                        bytes[i / 2] >> (4 * (1 - i % 2)) & 0x0f
+                    } else {
+                        bytes[i]
+                    };
+                    put(&mut x, y, index);
+                }
+                // absolute runs are padded to a 16-bit boundary
+                pos += len + len % 2;
+            }
+        }
+
+        if y >= height {
+            break;
+        }
+    }
+
+    Ok(indices)
+}
+
+impl Canvas {
+    /// Writes a Windows bitmap, bottom row first as the format expects.
+    /// `Rgba32` output takes the canvas's alpha plane, or is fully opaque
+    /// when it has none.
+    pub fn write_bmp<W: Write>(&self, writer: W, format: BmpFormat) -> io::Result<()> {
+        let (header_size, bits, compression) = match format {
+            BmpFormat::Rgb24 => (INFO_HEADER_SIZE, 24, BI_RGB),
+            BmpFormat::Rgba32 => (V4_HEADER_SIZE, 32, BI_BITFIELDS),
+            BmpFormat::Rle8 => (INFO_HEADER_SIZE, 8, BI_RLE8),
+        };
+
+        let mut palette: Vec<[u8; 3]> = vec![];
+        let mut pixels = vec![];
+
+        if format == BmpFormat::Rle8 {
+            let mut lookup = HashMap::new();
+            let mut indices = vec![0u8; self.width];
+
+            for y in (0..self.height).rev() {
+                for (index, color) in indices.iter_mut().zip(self.row(y)) {
+                    let bgr = to_bgr(color);
+                    let next = lookup.len();
+                    *index = *lookup.entry(bgr).or_insert_with(|| {
+                        palette.push(bgr);
+                        next
+                    }) as u8;
+
+                    if palette.len() > 256 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "RLE8 bitmaps can hold at most 256 colors",
+                        ));
+                    }
+                }
+                encode_rle8_row(&indices, &mut pixels);
+            }
+            // end of bitmap
+            pixels.extend([0, 1]);
+        } else {
+            let bytes_per_pixel = bits as usize / 8;
+            let stride = (self.width * bytes_per_pixel).next_multiple_of(4);
+
+            for y in (0..self.height).rev() {
+                let start = pixels.len();
+                for (x, color) in self.row(y).iter().enumerate() {
+                    pixels.extend(to_bgr(color));
+                    if bits == 32 {
+                        pixels.push(quantize(self.opacity(y * self.width + x), 255.0) as u8);
+                    }
+                }
+                pixels.resize(start + stride, 0);
+            }
+        }
+
+        let offset = FILE_HEADER_SIZE + header_size + palette.len() * 4;
+        let mut out = BufWriter::new(writer);
+
+        out.write_all(b"BM")?;
+        out.write_all(&((offset + pixels.len()) as u32).to_le_bytes())?;
+        out.write_all(&[0; 4])?;
+        out.write_all(&(offset as u32).to_le_bytes())?;
+
+        let mut info = Vec::with_capacity(header_size);
+        info.extend((header_size as u32).to_le_bytes());
+        info.extend((self.width as i32).to_le_bytes());
+        info.extend((self.height as i32).to_le_bytes());
+        info.extend(1u16.to_le_bytes());
+        info.extend((bits as u16).to_le_bytes());
+        info.extend(compression.to_le_bytes());
+        info.extend((pixels.len() as u32).to_le_bytes());
+        info.extend(RESOLUTION.to_le_bytes());
+        info.extend(RESOLUTION.to_le_bytes());
+        info.extend((palette.len() as u32).to_le_bytes());
+        info.extend(0u32.to_le_bytes());
+
+        if header_size == V4_HEADER_SIZE {
+            for mask in [0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+                info.extend(mask.to_le_bytes());
+            }
+            info.extend(b"BGRs");
+            // endpoints and gamma are unused for sRGB
+            info.resize(V4_HEADER_SIZE, 0);
+        }
+
+        out.write_all(&info)?;
+        for bgr in &palette {
+            out.write_all(bgr)?;
+            out.write_all(&[0])?;
+        }
+        out.write_all(&pixels)?;
+        out.flush()
+    }
+
+    /// Reads uncompressed 1/4/8/16/24/32-bit and RLE4/RLE8 bitmaps, in
+    /// either row order. An alpha mask, when the header has one, fills the
+    /// canvas's alpha plane unless every pixel is opaque.
+    pub fn from_bmp<R: Read>(mut reader: R) -> Result<Canvas, BmpError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        if !bytes.starts_with(b"BM") {
+            return Err(BmpError::NotBmp);
+        }
+
+        let header = Header::parse(&bytes)?;
+        let offset = u32_at(&bytes, 10)? as usize;
+        let data = bytes.get(offset..).ok_or(BmpError::Truncated)?;
+        let (width, height) = (header.width, header.height);
+        let too_large = || BmpError::Invalid("image is larger than its pixel data");
+        let pixels = width.checked_mul(height).ok_or_else(too_large)?;
+
+        // make sure uncompressed data can cover the image before allocating
+        // it; compressed images are only capped in size
+        let bits = header.bits as usize;
+        let stride = width
+            .checked_mul(bits)
+            .map(|row_bits| row_bits.div_ceil(32) * 4)
+            .ok_or_else(too_large)?;
+        let rle = header.compression == BI_RLE8 || header.compression == BI_RLE4;
+        if rle {
+            if pixels > MAX_RLE_PIXELS {
+                return Err(BmpError::Unsupported("compressed image is too large"));
+            }
+        } else if stride.checked_mul(height).ok_or_else(too_large)? > data.len() {
+            return Err(BmpError::Truncated);
+        }
+
+        let mut canvas = Canvas::new(width, height);
+
+        // Rows are stored bottom-up unless the height was negative.
+        let row_for = |stored: usize| {
+            if header.top_down {
+                stored
+            } else {
+                height - 1 - stored
+            }
+        };
+
+        if rle {
+            if header.top_down {
+                return Err(BmpError::Invalid("compressed bitmaps must be bottom-up"));
+            }
+
+            let indices = decode_rle(data, width, height, header.compression == BI_RLE4)?;
+            for (stored, row) in indices.chunks_exact(width).enumerate() {
+                for (x, &index) in row.iter().enumerate() {
+                    canvas.write_pixel(x, row_for(stored), header.color(index as usize)?);
+                }
+            }
+
+            return Ok(canvas);
+        }
+
+        let mut alpha = vec![1.0; pixels];
+
+        for stored in 0..height {
+            let row = data
+                .get(stored * stride..stored * stride + stride)
+                .ok_or(BmpError::Truncated)?;
+
+            for x in 0..width {
+                let packed = match bits {
+                    1 | 4 | 8 => {
+                        let bit = x * bits;
+                        let index = row[bit / 8] >> (8 - bits - bit % 8) & ((1 << bits) - 1) as u8;
+                        canvas.write_pixel(x, row_for(stored), header.color(index as usize)?);
+                        continue;
+                    }
+                    16 => u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]) as u32,
+                    24 => u32::from_le_bytes([row[x * 3], row[x * 3 + 1], row[x * 3 + 2], 0]),
+                    _ => u32::from_le_bytes([
+                        row[x * 4],
+                        row[x * 4 + 1],
+                        row[x * 4 + 2],
+                        row[x * 4 + 3],
+                    ]),
+                };
+
+                canvas.write_pixel(x, row_for(stored), header.unpack(packed));
+                if let Some(a) = header.unpack_alpha(packed) {
+                    alpha[row_for(stored) * width + x] = a;
+                }
+            }
+        }
+
+        canvas.alpha = alpha_plane(alpha);
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripes(width: usize, height: usize) -> Canvas {
+        let mut c = Canvas::new(width, height);
+        let palette = [
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+
+        for (x, y, color) in c.enumerate_pixels_mut() {
+            *color = if y == 0 {
+                palette[x % 4]
+            } else {
+                palette[(x / 5 + y) % 4]
+            };
+        }
+        c
+    }
+
+    fn round_trip(c: &Canvas, format: BmpFormat) -> Canvas {
+        let mut out = vec![];
+        c.write_bmp(&mut out, format).unwrap();
+        Canvas::from_bmp(&out[..]).unwrap()
+    }
+
+    #[test]
+    fn writing_24_bit_bitmaps_bottom_up() {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 0, Color::new(1.0, 0.0, 0.0));
+        c.write_pixel(1, 1, Color::new(0.0, 0.0, 1.0));
+
+        let mut out = vec![];
+        c.write_bmp(&mut out, BmpFormat::Rgb24).unwrap();
+
+        assert_eq!(&out[..2], b"BM");
+        assert_eq!(u32_at(&out, 2).unwrap() as usize, out.len());
+        assert_eq!(u32_at(&out, 10).unwrap(), 54);
+        // bottom row first, each row padded from 6 to 8 bytes
+        assert_eq!(&out[54..62], &[0, 0, 0, 255, 0, 0, 0, 0]);
+        assert_eq!(&out[62..70], &[0, 0, 255, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn round_tripping_every_format() {
+        let c = stripes(13, 7);
+
+        for format in [BmpFormat::Rgb24, BmpFormat::Rgba32, BmpFormat::Rle8] {
+            assert_eq!(round_trip(&c, format), c, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn run_length_encoding_shrinks_flat_images() {
+        let mut c = Canvas::new(200, 100);
+        c.fill(Color::new(0.0, 0.0, 1.0));
+        let (mut rle, mut raw) = (vec![], vec![]);
+
+        c.write_bmp(&mut rle, BmpFormat::Rle8).unwrap();
+        c.write_bmp(&mut raw, BmpFormat::Rgb24).unwrap();
+
+        assert!(rle.len() * 50 < raw.len());
+        assert_eq!(Canvas::from_bmp(&rle[..]).unwrap(), c);
+    }
+
+    #[test]
+    fn rle8_refuses_images_with_too_many_colors() {
+        let mut c = Canvas::new(300, 1);
+        for (x, _, color) in c.enumerate_pixels_mut() {
+            *color = Color::new(x as f64 / 299.0, (x % 2) as f64, 0.0);
+        }
+
+        let err = c.write_bmp(vec![], BmpFormat::Rle8).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    fn bitmap(info: &[u8], palette: &[[u8; 4]], data: &[u8]) -> Vec<u8> {
+        let offset = FILE_HEADER_SIZE + info.len() + palette.len() * 4;
+        let mut out = b"BM".to_vec();
+        out.extend(((offset + data.len()) as u32).to_le_bytes());
+        out.extend([0; 4]);
+        out.extend((offset as u32).to_le_bytes());
+        out.extend(info);
+        out.extend(palette.as_flattened());
+        out.extend(data);
+        out
+    }
+
+    fn info_header(width: i32, height: i32, bits: u16, compression: u32) -> Vec<u8> {
+        let mut info = vec![];
+        info.extend(40u32.to_le_bytes());
+        info.extend(width.to_le_bytes());
+        info.extend(height.to_le_bytes());
+        info.extend(1u16.to_le_bytes());
+        info.extend(bits.to_le_bytes());
+        info.extend(compression.to_le_bytes());
+        info.resize(40, 0);
+        info
+    }
+
+    #[test]
+    fn reading_top_down_palette_bitmaps() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let file = bitmap(
+            &info_header(3, -2, 1, BI_RGB),
+            &[[0, 0, 0, 0], [255, 255, 255, 0]],
+            &[0b0100_0000, 0, 0, 0, 0b1010_0000, 0, 0, 0],
+        );
+
+        let c = Canvas::from_bmp(&file[..]).unwrap();
+
+        assert_eq!(c.row(0), &[black, white, black]);
+        assert_eq!(c.row(1), &[white, black, white]);
+    }
+
+    #[test]
+    fn reading_rle4_with_deltas_and_absolute_runs() {
+        let palette = [[0, 0, 0, 0], [0, 0, 255, 0], [0, 255, 0, 0], [255, 0, 0, 0]];
+        let data = [
+            3, 0x12, // 1 2 1
+            0, 2, 1, 0, // delta: skip one pixel
+            0, 0, // end of line
+            0, 3, 0x32, 0x10, // absolute: 3 2 1, padded to even bytes
+            0, 1, // end of bitmap
+        ];
+        let file = bitmap(&info_header(5, 2, 4, BI_RLE4), &palette, &data);
+
+        let c = Canvas::from_bmp(&file[..]).unwrap();
+        let (black, red, green, blue) = (
+            Color::new(0.0, 0.0, 0.0),
+            Color::new(1.0, 0.0, 0.0),
+            Color::new(0.0, 1.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+        );
+
+        assert_eq!(c.row(1), &[red, green, red, black, black]);
+        assert_eq!(c.row(0), &[blue, green, red, black, black]);
+    }
+
+    #[test]
+    fn reading_16_bit_bitfields() {
+        let mut info = info_header(2, 1, 16, BI_BITFIELDS);
+        for mask in [0xf800u32, 0x07e0, 0x001f] {
+            info.extend(mask.to_le_bytes());
+        }
+        let file = bitmap(&info, &[], &[0x00, 0xf8, 0x1f, 0x00]);
+
+        let c = Canvas::from_bmp(&file[..]).unwrap();
+
+        assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn rejecting_invalid_files() {
+        let message = |file: &[u8]| Canvas::from_bmp(file).unwrap_err().to_string();
+
+        assert_eq!(message(b"P6 1 1 255"), "not a BMP file");
+        assert_eq!(message(b"BM\0\0"), "BMP file is truncated");
+        assert_eq!(
+            message(&bitmap(&info_header(1, 1, 24, 4), &[], &[0; 4])),
+            "unsupported BMP feature: compression method"
+        );
+        assert_eq!(
+            message(&bitmap(&info_header(1, 1, 24, BI_RGB), &[], &[0; 2])),
+            "BMP file is truncated"
+        );
+    }
+
+    #[test]
+    fn rejecting_headers_larger_than_the_data() {
+        let message = |file: &[u8]| Canvas::from_bmp(file).unwrap_err().to_string();
+
+        let huge = info_header(i32::MAX, i32::MAX, 24, BI_RGB);
+        assert_eq!(
+            message(&bitmap(&huge, &[], &[0; 16])),
+            "BMP file is truncated"
+        );
+
+        let palette = [[0, 0, 0, 0]; 2];
+        let huge = info_header(i32::MAX, i32::MAX, 8, BI_RLE8);
+        assert_eq!(
+            message(&bitmap(&huge, &palette, &[255, 0, 0, 1])),
+            "unsupported BMP feature: compressed image is too large"
+        );
+    }
+
+    #[test]
+    fn reading_sparse_rle_bitmaps() {
+        let palette = [[0, 0, 0, 0], [255, 255, 255, 0]];
+        // one white pixel, then end of bitmap; the rest stays index 0
+        let data = [1, 1, 0, 1];
+        let file = bitmap(&info_header(300, 200, 8, BI_RLE8), &palette, &data);
+
+        let c = Canvas::from_bmp(&file[..]).unwrap();
+        assert_eq!(c.pixel_at(0, 199), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(c.pixel_at(1, 199), Color::new(0.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(299, 0), Color::new(0.0, 0.0, 0.0));
+    }
+}
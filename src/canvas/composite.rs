@@ -0,0 +1,216 @@
+use super::Canvas;
+use crate::color::Color;
+
+/// Porter-Duff operators, named for what happens to the source.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Composite {
+    /// Source in front of the backdrop.
+    Over,
+    /// Source only where the backdrop is.
+    In,
+    /// Source only where the backdrop isn't.
+    Out,
+    /// Source over the backdrop, but only where the backdrop is.
+    Atop,
+    /// Source and backdrop where the other isn't.
+    Xor,
+}
+
+impl Composite {
+    /// The fractions of the source and backdrop that survive.
+    fn factors(&self, source_alpha: f64, backdrop_alpha: f64) -> (f64, f64) {
+        match self {
+            Composite::Over => (1.0, 1.0 - source_alpha),
+            Composite::In => (backdrop_alpha, 0.0),
+            Composite::Out => (1.0 - backdrop_alpha, 0.0),
+            Composite::Atop => (backdrop_alpha, 1.0 - source_alpha),
+            Composite::Xor => (1.0 - backdrop_alpha, 1.0 - source_alpha),
+        }
+    }
+}
+
+/// Separable blend modes, mixing the source color with the backdrop before
+/// compositing it over.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Blend {
+    Normal,
+    Multiply,
+    Screen,
+    /// Multiply in the backdrop's shadows, screen in its highlights.
+    Overlay,
+}
+
+impl Blend {
+    fn mix(&self, backdrop: f64, source: f64) -> f64 {
+        match self {
+            Blend::Normal => source,
+            Blend::Multiply => backdrop * source,
+            Blend::Screen => backdrop + source - backdrop * source,
+            Blend::Overlay if backdrop <= 0.5 => Blend::Multiply.mix(2.0 * backdrop, source),
+            Blend::Overlay => Blend::Screen.mix(2.0 * backdrop - 1.0, source),
+        }
+    }
+}
+
+impl Canvas {
+    /// Combines this canvas, as the source, with `backdrop` pixel by pixel
+    /// and returns the result. Panics if the sizes differ.
+    pub fn composite(&self, backdrop: &Canvas, operator: Composite) -> Canvas {
+        self.combine(backdrop, |source, source_alpha, under, under_alpha| {
+            let (fa, fb) = operator.factors(source_alpha, under_alpha);
+            let color = source.premultiply(source_alpha * fa) + under.premultiply(under_alpha * fb);
+
+            (color, source_alpha * fa + under_alpha * fb)
+        })
+    }
+
+    /// Blends this canvas with `backdrop` using `mode` and composites the
+    /// result over it, following the W3C compositing model. Where either
+    /// side is transparent the other shows through unchanged.
+    pub fn blend(&self, backdrop: &Canvas, mode: Blend) -> Canvas {
+        self.combine(backdrop, |source, source_alpha, under, under_alpha| {
+            let mixed = Color::new(
+                mode.mix(under.red, source.red),
+                mode.mix(under.green, source.green),
+                mode.mix(under.blue, source.blue),
+            );
+
+            let color = source.premultiply(source_alpha * (1.0 - under_alpha))
+                + under.premultiply(under_alpha * (1.0 - source_alpha))
+                + mixed.premultiply(source_alpha * under_alpha);
+
+            (color, source_alpha + under_alpha * (1.0 - source_alpha))
+        })
+    }
+
+    /// Runs `f` on straight colors and alphas of both canvases, where `f`
+    /// returns a premultiplied color and its alpha.
+    fn combine<F>(&self, backdrop: &Canvas, f: F) -> Canvas
+    where
+        F: Fn(Color, f64, Color, f64) -> (Color, f64),
+    {
+        assert!(
+            self.width == backdrop.width && self.height == backdrop.height,
+            "source is {}x{} but the backdrop is {}x{}",
+            self.width,
+            self.height,
+            backdrop.width,
+            backdrop.height
+        );
+
+        let mut result = Canvas::new(self.width, self.height);
+        let mut alpha = Vec::with_capacity(self.data.len());
+
+        for (i, color) in result.data.iter_mut().enumerate() {
+            let (premultiplied, a) = f(
+                self.data[i],
+                self.opacity(i),
+                backdrop.data[i],
+                backdrop.opacity(i),
+            );
+
+            *color = premultiplied.unpremultiply(a);
+            alpha.push(a);
+        }
+
+        if self.alpha.is_some() || backdrop.alpha.is_some() {
+            result.alpha = Some(alpha);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    fn pixel(color: Color, alpha: f64) -> Canvas {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.write_pixel(0, 0, color);
+        canvas.write_alpha(0, 0, alpha);
+        canvas
+    }
+
+    fn assert_pixel(canvas: &Canvas, color: Color, alpha: f64) {
+        let actual = canvas.pixel_at(0, 0);
+        assert!(
+            equal(actual.red, color.red)
+                && equal(actual.green, color.green)
+                && equal(actual.blue, color.blue),
+            "{:?} != {:?}",
+            actual,
+            color
+        );
+        assert!(equal(canvas.alpha_at(0, 0), alpha));
+    }
+
+    #[test]
+    fn porter_duff_operators() {
+        let red = pixel(Color::new(1.0, 0.0, 0.0), 0.5);
+        let blue = pixel(Color::new(0.0, 0.0, 1.0), 0.5);
+
+        // over: 0.5 red + 0.25 blue, alpha 0.75
+        let over = red.composite(&blue, Composite::Over);
+        assert_pixel(&over, Color::new(2.0 / 3.0, 0.0, 1.0 / 3.0), 0.75);
+
+        let inside = red.composite(&blue, Composite::In);
+        assert_pixel(&inside, Color::new(1.0, 0.0, 0.0), 0.25);
+
+        let outside = red.composite(&blue, Composite::Out);
+        assert_pixel(&outside, Color::new(1.0, 0.0, 0.0), 0.25);
+
+        let atop = red.composite(&blue, Composite::Atop);
+        assert_pixel(&atop, Color::new(0.5, 0.0, 0.5), 0.5);
+
+        let xor = red.composite(&blue, Composite::Xor);
+        assert_pixel(&xor, Color::new(0.5, 0.0, 0.5), 0.5);
+    }
+
+    #[test]
+    fn compositing_over_an_opaque_backdrop() {
+        let source = pixel(Color::new(1.0, 1.0, 1.0), 0.25);
+        let mut backdrop = Canvas::new(1, 1);
+        backdrop.fill(Color::new(0.0, 0.4, 0.0));
+
+        let result = source.composite(&backdrop, Composite::Over);
+        assert_pixel(&result, Color::new(0.25, 0.55, 0.25), 1.0);
+
+        // two opaque canvases stay opaque, and the source wins
+        let opaque = backdrop.composite(&Canvas::new(1, 1), Composite::Over);
+        assert_eq!(opaque, backdrop);
+    }
+
+    #[test]
+    fn blend_modes() {
+        let source = pixel(Color::new(0.5, 0.2, 1.0), 1.0);
+        let backdrop = pixel(Color::new(0.5, 0.8, 0.25), 1.0);
+
+        let multiply = source.blend(&backdrop, Blend::Multiply);
+        assert_pixel(&multiply, Color::new(0.25, 0.16, 0.25), 1.0);
+
+        let screen = source.blend(&backdrop, Blend::Screen);
+        assert_pixel(&screen, Color::new(0.75, 0.84, 1.0), 1.0);
+
+        let overlay = source.blend(&backdrop, Blend::Overlay);
+        assert_pixel(&overlay, Color::new(0.5, 0.68, 0.5), 1.0);
+
+        let normal = source.blend(&backdrop, Blend::Normal);
+        assert_eq!(normal, source.composite(&backdrop, Composite::Over));
+    }
+
+    #[test]
+    fn blending_over_transparent_pixels() {
+        let source = pixel(Color::new(0.5, 0.5, 0.5), 1.0);
+        let backdrop = pixel(Color::new(1.0, 0.0, 0.0), 0.0);
+
+        let result = source.blend(&backdrop, Blend::Multiply);
+        assert_pixel(&result, Color::new(0.5, 0.5, 0.5), 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "source is 2x1 but the backdrop is 1x1")]
+    fn rejecting_mismatched_canvases() {
+        Canvas::new(2, 1).composite(&Canvas::new(1, 1), Composite::Over);
+    }
+}
@@ -0,0 +1,840 @@
+//! A small DEFLATE (RFC 1951) codec with zlib (RFC 1950) framing, shared by
+//! the image formats that need compression.
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+
+const MAX_BITS: usize = 15;
+const WINDOW_SIZE: usize = 1 << 15;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 64;
+const BLOCK_TOKENS: usize = 1 << 16;
+const END_OF_BLOCK: usize = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are stored in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct InflateError(&'static str);
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corrupt compressed data: {}", self.0)
+    }
+}
+
+impl Error for InflateError {}
+
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // 5552 bytes is the most that can be summed before `b` overflows.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+
+    b << 16 | a
+}
+
+/// Compresses `data` into a zlib stream.
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x9c];
+    out.extend(deflate(data));
+    out.extend(adler32(data).to_be_bytes());
+
+    out
+}
+
+/// Decompresses a zlib stream, checking its header and Adler-32 trailer.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, InflateError> {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf, *flg),
+        _ => return Err(InflateError("missing zlib header")),
+    };
+
+    if cmf & 0x0f != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(InflateError("invalid zlib header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(InflateError("preset dictionaries are not supported"));
+    }
+
+    let (out, used) = inflate_stream(&data[2..])?;
+    let trailer = data
+        .get(2 + used..2 + used + 4)
+        .ok_or(InflateError("missing Adler-32 checksum"))?;
+
+    if u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) != adler32(&out) {
+        return Err(InflateError("Adler-32 checksum mismatch"));
+    }
+
+    Ok(out)
+}
+
+/// Decompresses a raw DEFLATE stream.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, InflateError> {
+    inflate_stream(data).map(|(out, _)| out)
+}
+
+/// Compresses `data` as a raw DEFLATE stream of dynamic Huffman blocks.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let tokens = lz77(data);
+    let mut writer = BitWriter::new();
+    let mut blocks = tokens.chunks(BLOCK_TOKENS).peekable();
+
+    if blocks.peek().is_none() {
+        write_block(&mut writer, &[], true);
+    }
+
+    while let Some(block) = blocks.next() {
+        write_block(&mut writer, block, blocks.peek().is_none());
+    }
+
+    writer.finish()
+}
+
+// --- decoding ---
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        while self.bit_count < count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(InflateError("unexpected end of stream"))?;
+            self.pos += 1;
+            self.bit_buffer |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+
+        Ok(value)
+    }
+
+    /// Drops any bits left in the current byte.
+    fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length
+/// and the symbols sorted by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Incomplete codes are allowed (e.g. a single distance code), but
+        // over-subscribed ones can't be decoded.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(InflateError("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<usize, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = count as i32;
+
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize] as usize);
+            }
+
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(InflateError("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+
+    (
+        Huffman::new(&lengths).expect("fixed literal code is valid"),
+        Huffman::new(&[5; 30]).expect("fixed distance code is valid"),
+    )
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+
+    if literal_count > 286 || distance_count > 30 {
+        return Err(InflateError("too many length or distance codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![];
+    while lengths.len() < literal_count + distance_count {
+        let (length, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or(InflateError("repeated length with no previous length"))?;
+                (previous, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+
+        lengths.extend(std::iter::repeat_n(length, repeat as usize));
+    }
+
+    if lengths.len() > literal_count + distance_count {
+        return Err(InflateError("code lengths overflow the header counts"));
+    }
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(InflateError("missing end-of-block code"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = literals.decode(reader)?;
+
+        if symbol < END_OF_BLOCK {
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            return Ok(());
+        }
+
+        let index = symbol - 257;
+        if index >= LENGTH_BASE.len() {
+            return Err(InflateError("invalid length code"));
+        }
+        let length =
+            LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+        let index = distances.decode(reader)?;
+        if index >= DISTANCE_BASE.len() {
+            return Err(InflateError("invalid distance code"));
+        }
+        let distance =
+            DISTANCE_BASE[index] as usize + reader.bits(DISTANCE_EXTRA[index] as u32)? as usize;
+
+        if distance > out.len() {
+            return Err(InflateError("distance reaches before start of output"));
+        }
+
+        // Copies may overlap their own output, so go a byte at a time.
+        let start = out.len() - distance;
+        for i in 0..length {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+/// Returns the decompressed bytes and how many input bytes were used.
+fn inflate_stream(data: &[u8]) -> Result<(Vec<u8>, usize), InflateError> {
+    let mut reader = BitReader::new(data);
+    let mut out = vec![];
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or(InflateError("unexpected end of stream"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+
+                if len != !nlen {
+                    return Err(InflateError("stored block length mismatch"));
+                }
+
+                let start = reader.pos + 4;
+                let stored = data
+                    .get(start..start + len as usize)
+                    .ok_or(InflateError("unexpected end of stream"))?;
+                out.extend_from_slice(stored);
+                reader.pos = start + len as usize;
+            }
+            1 => {
+                let (literals, distances) = fixed_codes();
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(InflateError("invalid block type")),
+        }
+
+        if last {
+            return Ok((out, reader.pos));
+        }
+    }
+}
+
+// --- encoding ---
+
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: vec![],
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Huffman codes are packed starting from their most significant bit.
+    fn code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.bits(reversed as u32, length as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buffer as u8);
+        }
+
+        self.out
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn length_code(length: u16) -> usize {
+    LENGTH_BASE
+        .iter()
+        .rposition(|&base| base <= length)
+        .unwrap()
+}
+
+fn distance_code(distance: u16) -> usize {
+    DISTANCE_BASE
+        .iter()
+        .rposition(|&base| base <= distance)
+        .unwrap()
+}
+
+fn hash(data: &[u8]) -> usize {
+    let value = (data[0] as usize) << 16 | (data[1] as usize) << 8 | data[2] as usize;
+    (value.wrapping_mul(2654435761) >> 8) & ((1 << HASH_BITS) - 1)
+}
+
+/// Greedy LZ77 over a 32K window with hash chains.
+fn lz77(data: &[u8]) -> Vec<Token> {
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; WINDOW_SIZE];
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    let insert = |head: &mut [usize], prev: &mut [usize], pos: usize| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(&data[pos..]);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    while pos < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+
+        if pos + MIN_MATCH <= data.len() {
+            let max_length = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash(&data[pos..])];
+            let mut chain = 0;
+
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+
+                let next = prev[candidate % WINDOW_SIZE];
+                if next == usize::MAX || next >= candidate {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_length >= MIN_MATCH {
+            tokens.push(Token::Match {
+                length: best_length as u16,
+                distance: best_distance as u16,
+            });
+            for p in pos..pos + best_length {
+                insert(&mut head, &mut prev, p);
+            }
+            pos += best_length;
+        } else {
+            tokens.push(Token::Literal(data[pos]));
+            insert(&mut head, &mut prev, pos);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Builds Huffman code lengths for `frequencies`, no longer than `limit`.
+fn code_lengths(frequencies: &[u32], limit: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+
+    loop {
+        let mut heap = BinaryHeap::new();
+        let mut parents = vec![];
+
+        for (symbol, &frequency) in frequencies.iter().enumerate() {
+            if frequency > 0 {
+                heap.push(Reverse((frequency as u64, parents.len(), symbol)));
+                parents.push(usize::MAX);
+            }
+        }
+
+        let mut lengths = vec![0u8; frequencies.len()];
+        if heap.len() == 1 {
+            let Reverse((_, _, symbol)) = heap.pop().unwrap();
+            lengths[symbol] = 1;
+            return lengths;
+        }
+
+        let leaves: Vec<usize> = frequencies
+            .iter()
+            .enumerate()
+            .filter(|(_, &f)| f > 0)
+            .map(|(symbol, _)| symbol)
+            .collect();
+
+        while heap.len() > 1 {
+            let Reverse((a_weight, a, _)) = heap.pop().unwrap();
+            let Reverse((b_weight, b, _)) = heap.pop().unwrap();
+            let node = parents.len();
+            parents.push(usize::MAX);
+            parents[a] = node;
+            parents[b] = node;
+            heap.push(Reverse((a_weight + b_weight, node, usize::MAX)));
+        }
+
+        let mut longest = 0;
+        for (node, &symbol) in leaves.iter().enumerate() {
+            let mut depth = 0;
+            let mut current = node;
+            while parents[current] != usize::MAX {
+                current = parents[current];
+                depth += 1;
+            }
+            lengths[symbol] = depth;
+            longest = longest.max(depth);
+        }
+
+        if longest <= limit {
+            return lengths;
+        }
+
+        // Flatten the distribution and try again.
+        for frequency in frequencies.iter_mut().filter(|f| **f > 0) {
+            *frequency = (*frequency >> 1).max(1);
+        }
+    }
+}
+
+/// Assigns canonical codes to a set of code lengths.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_BITS + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next = [0u16; MAX_BITS + 1];
+    let mut code = 0;
+    for length in 1..=MAX_BITS {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[length as usize];
+            next[length as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Makes sure at least two symbols get a code so the result is complete.
+fn ensure_two_symbols(frequencies: &mut [u32]) {
+    if frequencies.iter().filter(|&&f| f > 0).count() < 2 {
+        for frequency in frequencies.iter_mut().take(2) {
+            *frequency = (*frequency).max(1);
+        }
+    }
+}
+
+/// Run-length encodes code lengths with the 16/17/18 repeat symbols.
+/// Returns `(symbol, extra bits value)` pairs.
+fn run_length_encode(lengths: &[u8]) -> Vec<(usize, u32)> {
+    let mut symbols = vec![];
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let length = lengths[i];
+        let mut run = lengths[i..].iter().take_while(|&&l| l == length).count();
+        i += run;
+
+        if length == 0 {
+            while run >= 11 {
+                let repeat = run.min(138);
+                symbols.push((18, (repeat - 11) as u32));
+                run -= repeat;
+            }
+            if run >= 3 {
+                symbols.push((17, (run - 3) as u32));
+                run = 0;
+            }
+        } else {
+            symbols.push((length as usize, 0));
+            run -= 1;
+            while run >= 3 {
+                let repeat = run.min(6);
+                symbols.push((16, (repeat - 3) as u32));
+                run -= repeat;
+            }
+        }
+
+        symbols.extend(std::iter::repeat_n((length as usize, 0), run));
+    }
+
+    symbols
+}
+
+fn write_block(writer: &mut BitWriter, tokens: &[Token], last: bool) {
+    let mut literal_frequencies = [0u32; 286];
+    let mut distance_frequencies = [0u32; 30];
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_frequencies[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literal_frequencies[257 + length_code(length)] += 1;
+                distance_frequencies[distance_code(distance)] += 1;
+            }
+        }
+    }
+    literal_frequencies[END_OF_BLOCK] += 1;
+    ensure_two_symbols(&mut literal_frequencies);
+    ensure_two_symbols(&mut distance_frequencies);
+
+    let literal_lengths = code_lengths(&literal_frequencies, MAX_BITS as u8);
+    let distance_lengths = code_lengths(&distance_frequencies, MAX_BITS as u8);
+    let literal_codes = canonical_codes(&literal_lengths);
+    let distance_codes = canonical_codes(&distance_lengths);
+
+    let literal_count = 257.max(literal_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+    let distance_count = 1.max(distance_lengths.iter().rposition(|&l| l > 0).unwrap() + 1);
+
+    let all_lengths: Vec<u8> = literal_lengths[..literal_count]
+        .iter()
+        .chain(&distance_lengths[..distance_count])
+        .copied()
+        .collect();
+    let length_symbols = run_length_encode(&all_lengths);
+
+    let mut code_length_frequencies = [0u32; 19];
+    for &(symbol, _) in &length_symbols {
+        code_length_frequencies[symbol] += 1;
+    }
+    ensure_two_symbols(&mut code_length_frequencies);
+    let code_length_lengths = code_lengths(&code_length_frequencies, 7);
+    let code_length_codes = canonical_codes(&code_length_lengths);
+    let code_length_count = 4.max(
+        CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| code_length_lengths[symbol] > 0)
+            .unwrap()
+            + 1,
+    );
+
+    writer.bits(last as u32, 1);
+    writer.bits(2, 2);
+    writer.bits((literal_count - 257) as u32, 5);
+    writer.bits((distance_count - 1) as u32, 5);
+    writer.bits((code_length_count - 4) as u32, 4);
+
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        writer.bits(code_length_lengths[symbol] as u32, 3);
+    }
+
+    for &(symbol, extra) in &length_symbols {
+        writer.code(code_length_codes[symbol], code_length_lengths[symbol]);
+        match symbol {
+            16 => writer.bits(extra, 2),
+            17 => writer.bits(extra, 3),
+            18 => writer.bits(extra, 7),
+            _ => {}
+        }
+    }
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                writer.code(literal_codes[byte as usize], literal_lengths[byte as usize])
+            }
+            Token::Match { length, distance } => {
+                let index = length_code(length);
+                writer.code(literal_codes[257 + index], literal_lengths[257 + index]);
+                writer.bits(
+                    (length - LENGTH_BASE[index]) as u32,
+                    LENGTH_EXTRA[index] as u32,
+                );
+
+                let index = distance_code(distance);
+                writer.code(distance_codes[index], distance_lengths[index]);
+                writer.bits(
+                    (distance - DISTANCE_BASE[index]) as u32,
+                    DISTANCE_EXTRA[index] as u32,
+                );
+            }
+        }
+    }
+
+    writer.code(literal_codes[END_OF_BLOCK], literal_lengths[END_OF_BLOCK]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545f491u32;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adler32_checksum() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+    }
+
+    #[test]
+    fn decompressing_stored_fixed_and_dynamic_blocks() {
+        let stored = hex("7801010c00f3ff73746f72656420626c6f636b1f8004bd");
+        assert_eq!(zlib_decompress(&stored).unwrap(), b"stored block");
+
+        let fixed = hex("78dacb48cdc9c957c8402701680308b1");
+        assert_eq!(zlib_decompress(&fixed).unwrap(), b"hello hello hello hello");
+
+        let dynamic = hex(concat!(
+            "78da95d1c91582501404d1545e083688403820c8289f798a5e8e1150cb3e5dbb",
+            "3b97b90d4bf56e2c1dddd6d9c7ed562fdf7e32b7e6a3cdd7dd26e761992becf1",
+            "9f776ba1da43b58fea27aa0354bf501da23a4275cc742026d314e314f3140315",
+            "1315231533154355fc0387254a68"
+        ));
+        let expected: Vec<String> = (0..20)
+            .map(|i| format!("the quick brown fox jumps over the lazy dog {}", i))
+            .collect();
+        assert_eq!(
+            zlib_decompress(&dynamic).unwrap(),
+            expected.join(" ").as_bytes()
+        );
+    }
+
+    #[test]
+    fn round_tripping_through_deflate() {
+        let repetitive: Vec<u8> = (0..100_000).map(|i| (i % 251 / 7) as u8).collect();
+
+        for data in [
+            vec![],
+            vec![42],
+            b"abcabcabcabc".to_vec(),
+            noise(70_000),
+            repetitive,
+        ] {
+            let compressed = zlib_compress(&data);
+            assert_eq!(zlib_decompress(&compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn compressing_repetitive_data() {
+        let data = vec![7u8; 50_000];
+
+        assert!(zlib_compress(&data).len() < 200);
+    }
+
+    #[test]
+    fn rejecting_corrupt_streams() {
+        let mut compressed = zlib_compress(b"some bytes to check");
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+
+        assert_eq!(
+            zlib_decompress(&compressed),
+            Err(InflateError("Adler-32 checksum mismatch"))
+        );
+        assert_eq!(
+            zlib_decompress(&[0x78, 0x9c, 0x07]),
+            Err(InflateError("invalid block type"))
+        );
+        assert_eq!(
+            zlib_decompress(&[0x78, 0x9d]),
+            Err(InflateError("invalid zlib header"))
+        );
+    }
+
+    #[test]
+    fn limiting_code_lengths() {
+        let frequencies: Vec<u32> = (0..30).map(|i| 1 << i.min(25)).collect();
+        let lengths = code_lengths(&frequencies, 7);
+
+        assert!(lengths.iter().all(|&l| (1..=7).contains(&l)));
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(l as i32)).sum();
+        assert!(kraft <= 1.0);
+    }
+}
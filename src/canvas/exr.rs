@@ -0,0 +1,639 @@
+use std::io::{self, BufWriter, Write};
+
+use super::deflate::zlib_compress;
+use super::Canvas;
+use crate::framebuffer::FrameBuffer;
+
+pub(super) const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+/// Version 2, single-part scanline file, no flags.
+const VERSION: [u8; 4] = [2, 0, 0, 0];
+
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 127;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExrPixelType {
+    /// 16-bit IEEE half floats.
+    Half,
+    /// 32-bit IEEE floats.
+    Float,
+}
+
+impl ExrPixelType {
+    fn id(&self) -> i32 {
+        match self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExrCompression {
+    None,
+    Rle,
+    /// zlib, one scanline per chunk.
+    Zips,
+    /// zlib, sixteen scanlines per chunk.
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Rle => 1,
+            ExrCompression::Zips => 2,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_chunk(&self) -> usize {
+        match self {
+            ExrCompression::Zip => 16,
+            _ => 1,
+        }
+    }
+}
+
+/// Converts to an IEEE half float, rounding to nearest even. Values too
+/// large for a half become infinity.
+fn to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = (bits >> 16 & 0x8000) as u16;
+    let exponent = (bits >> 23 & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+
+        // subnormal: shift the full mantissa down into units of 2^-24
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = remainder > halfway || (remainder == halfway && half & 1 == 1);
+
+        return sign | (half + round_up as u32) as u16;
+    }
+
+    let half = (half_exponent as u32) << 10 | mantissa >> 13;
+    let remainder = mantissa & 0x1fff;
+    let round_up = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+
+    // a carry out of the mantissa correctly bumps the exponent
+    sign | (half + round_up as u32) as u16
+}
+
+/// Splits the bytes into even and odd halves and delta-encodes them, which
+/// both the RLE and ZIP schemes do before compressing.
+fn predict(raw: &[u8]) -> Vec<u8> {
+    let half = raw.len().div_ceil(2);
+    let mut reordered = vec![0; raw.len()];
+
+    for (i, &byte) in raw.iter().enumerate() {
+        let target = if i % 2 == 0 { i / 2 } else { half + i / 2 };
+        reordered[target] = byte;
+    }
+
+    let mut previous = reordered.first().copied().unwrap_or(0);
+    for byte in reordered.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = current.wrapping_sub(previous).wrapping_add(128);
+        previous = current;
+    }
+
+    reordered
+}
+
+/// OpenEXR's byte RLE: a non-negative count `n` repeats the next byte
+/// `n + 1` times, a negative count `-n` is followed by `n` literal bytes.
+fn run_length_encode(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut start = 0;
+
+    while start < data.len() {
+        let run = data[start..]
+            .iter()
+            .take(MAX_RUN + 1)
+            .take_while(|&&b| b == data[start])
+            .count();
+
+        if run >= MIN_RUN {
+            out.extend([(run - 1) as u8, data[start]]);
+            start += run;
+            continue;
+        }
+
+        let mut end = start;
+        while end < data.len() && end - start < MAX_RUN {
+            if end + 2 < data.len() && data[end] == data[end + 1] && data[end] == data[end + 2] {
+                break;
+            }
+            end += 1;
+        }
+
+        out.push((-((end - start) as i32)) as u8);
+        out.extend_from_slice(&data[start..end]);
+        start = end;
+    }
+
+    out
+}
+
+fn write_attribute<W: Write>(out: &mut W, name: &str, kind: &str, value: &[u8]) -> io::Result<()> {
+    out.write_all(name.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(kind.as_bytes())?;
+    out.write_all(&[0])?;
+    out.write_all(&(value.len() as i32).to_le_bytes())?;
+    out.write_all(value)
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+impl FrameBuffer {
+    /// Writes every layer into one single-part scanline OpenEXR file.
+    ///
+    /// All channels share `pixel_type`. Fails with `InvalidInput` when two
+    /// layers produce the same channel name, the buffer has no layers or
+    /// it has no pixels, which an EXR data window can't describe.
+    pub fn write_exr<W: Write>(
+        &self,
+        writer: W,
+        pixel_type: ExrPixelType,
+        compression: ExrCompression,
+    ) -> io::Result<()> {
+        // (channel name, layer, channel within layer), sorted by name as
+        // the format requires
+        let mut channels = vec![];
+        for (layer_index, layer) in self.layers.iter().enumerate() {
+            for (channel, name) in layer.channel_names().into_iter().enumerate() {
+                channels.push((name, layer_index, channel));
+            }
+        }
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        if channels.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an EXR file needs at least one channel",
+            ));
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "an EXR file needs at least one pixel",
+            ));
+        }
+        if let Some(pair) = channels.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("duplicate EXR channel {:?}", pair[0].0),
+            ));
+        }
+
+        let mut header = vec![];
+        header.extend(MAGIC);
+        header.extend(VERSION);
+
+        let mut channel_list = vec![];
+        for (name, _, _) in &channels {
+            channel_list.extend(name.as_bytes());
+            channel_list.push(0);
+            channel_list.extend(pixel_type.id().to_le_bytes());
+            // pLinear and three reserved bytes
+            channel_list.extend([0; 4]);
+            // x and y sampling
+            channel_list.extend(1i32.to_le_bytes());
+            channel_list.extend(1i32.to_le_bytes());
+        }
+        channel_list.push(0);
+
+        let window = box2i(self.width, self.height);
+        write_attribute(&mut header, "channels", "chlist", &channel_list)?;
+        write_attribute(
+            &mut header,
+            "compression",
+            "compression",
+            &[compression.id()],
+        )?;
+        write_attribute(&mut header, "dataWindow", "box2i", &window)?;
+        write_attribute(&mut header, "displayWindow", "box2i", &window)?;
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0])?;
+        write_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        )?;
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+        write_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        )?;
+        header.push(0);
+
+        let lines = compression.lines_per_chunk();
+        let mut chunks = vec![];
+
+        for first_line in (0..self.height).step_by(lines) {
+            let last_line = (first_line + lines).min(self.height);
+            let mut raw = vec![];
+
+            // Within a chunk each scanline stores all of one channel's
+            // pixels before moving on to the next channel.
+            for y in first_line..last_line {
+                for &(_, layer, channel) in &channels {
+                    for x in 0..self.width {
+                        let sample = self.layers[layer].sample(channel, y * self.width + x) as f32;
+                        match pixel_type {
+                            ExrPixelType::Half => raw.extend(to_half(sample).to_le_bytes()),
+                            ExrPixelType::Float => raw.extend(sample.to_le_bytes()),
+                        }
+                    }
+                }
+            }
+
+            let compressed = match compression {
+                ExrCompression::None => None,
+                ExrCompression::Rle => Some(run_length_encode(&predict(&raw))),
+                ExrCompression::Zips | ExrCompression::Zip => Some(zlib_compress(&predict(&raw))),
+            };
+
+            // Readers treat a chunk that isn't smaller than its raw size as
+            // stored uncompressed.
+            let data = match compressed {
+                Some(data) if data.len() < raw.len() => data,
+                _ => raw,
+            };
+
+            let mut chunk = vec![];
+            chunk.extend((first_line as i32).to_le_bytes());
+            chunk.extend((data.len() as i32).to_le_bytes());
+            chunk.extend(data);
+            chunks.push(chunk);
+        }
+
+        let mut out = BufWriter::new(writer);
+        out.write_all(&header)?;
+
+        let mut offset = (header.len() + chunks.len() * 8) as u64;
+        for chunk in &chunks {
+            out.write_all(&offset.to_le_bytes())?;
+            offset += chunk.len() as u64;
+        }
+        for chunk in &chunks {
+            out.write_all(chunk)?;
+        }
+
+        out.flush()
+    }
+}
+
+impl Canvas {
+    /// Writes the canvas as the `R`, `G` and `B` channels of an OpenEXR
+    /// file, plus `A` if it has an alpha plane. EXR colors are premultiplied
+    /// by alpha. Use a `FrameBuffer` to store more layers alongside it.
+    pub fn write_exr<W: Write>(
+        &self,
+        writer: W,
+        pixel_type: ExrPixelType,
+        compression: ExrCompression,
+    ) -> io::Result<()> {
+        let frame = match &self.alpha {
+            None => FrameBuffer::from_canvas(self.clone()),
+            Some(alpha) => {
+                let mut premultiplied = self.clone();
+                for (color, &a) in premultiplied.data.iter_mut().zip(alpha) {
+                    *color = color.premultiply(a);
+                }
+
+                let mut frame = FrameBuffer::from_canvas(premultiplied);
+                frame.add_scalar_layer("A", alpha.clone());
+                frame
+            }
+        };
+
+        frame.write_exr(writer, pixel_type, compression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::deflate::zlib_decompress;
+    use super::*;
+    use crate::color::Color;
+
+    fn from_half(half: u16) -> f32 {
+        let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = (half >> 10 & 0x1f) as i32;
+        let mantissa = (half & 0x3ff) as f32;
+
+        sign * match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1f if mantissa == 0.0 => f32::INFINITY,
+            0x1f => f32::NAN,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    fn unpredict(data: &[u8]) -> Vec<u8> {
+        let mut deltas = data.to_vec();
+        for i in 1..deltas.len() {
+            deltas[i] = deltas[i - 1].wrapping_add(deltas[i]).wrapping_sub(128);
+        }
+
+        let half = deltas.len().div_ceil(2);
+        (0..deltas.len())
+            .map(|i| {
+                if i % 2 == 0 {
+                    deltas[i / 2]
+                } else {
+                    deltas[half + i / 2]
+                }
+            })
+            .collect()
+    }
+
+    fn run_length_decode(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        let mut i = 0;
+        while i < data.len() {
+            let count = data[i] as i8;
+            if count < 0 {
+                out.extend_from_slice(&data[i + 1..i + 1 + (-count) as usize]);
+                i += 1 + (-count) as usize;
+            } else {
+                out.extend(std::iter::repeat_n(data[i + 1], count as usize + 1));
+                i += 2;
+            }
+        }
+        out
+    }
+
+    struct Parsed {
+        attributes: Vec<(String, String, Vec<u8>)>,
+        chunks: Vec<(i32, Vec<u8>)>,
+    }
+
+    /// Just enough of a reader to check what the writer produced.
+    fn parse(file: &[u8], compression: ExrCompression, raw_chunk_len: usize) -> Parsed {
+        assert_eq!(&file[..4], &MAGIC);
+        assert_eq!(&file[4..8], &VERSION);
+
+        let mut pos = 8;
+        let mut attributes = vec![];
+        let string = |pos: &mut usize| {
+            let end = *pos + file[*pos..].iter().position(|&b| b == 0).unwrap();
+            let text = String::from_utf8(file[*pos..end].to_vec()).unwrap();
+            *pos = end + 1;
+            text
+        };
+
+        while file[pos] != 0 {
+            let name = string(&mut pos);
+            let kind = string(&mut pos);
+            let size = i32::from_le_bytes(file[pos..pos + 4].try_into().unwrap()) as usize;
+            attributes.push((name, kind, file[pos + 4..pos + 4 + size].to_vec()));
+            pos += 4 + size;
+        }
+        pos += 1;
+
+        let mut chunks = vec![];
+        let mut offsets = vec![];
+        loop {
+            let offset = u64::from_le_bytes(file[pos..pos + 8].try_into().unwrap()) as usize;
+            if !offsets.is_empty() && offsets[0] == pos {
+                break;
+            }
+            offsets.push(offset);
+            pos += 8;
+        }
+
+        for offset in offsets {
+            let y = i32::from_le_bytes(file[offset..offset + 4].try_into().unwrap());
+            let size =
+                i32::from_le_bytes(file[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            let data = &file[offset + 8..offset + 8 + size];
+
+            let raw = if size >= raw_chunk_len {
+                data.to_vec()
+            } else {
+                match compression {
+                    ExrCompression::None => data.to_vec(),
+                    ExrCompression::Rle => unpredict(&run_length_decode(data)),
+                    _ => unpredict(&zlib_decompress(data).unwrap()),
+                }
+            };
+            chunks.push((y, raw));
+        }
+
+        Parsed { attributes, chunks }
+    }
+
+    #[test]
+    fn converting_to_half_floats() {
+        assert_eq!(to_half(0.0), 0x0000);
+        assert_eq!(to_half(-0.0), 0x8000);
+        assert_eq!(to_half(1.0), 0x3c00);
+        assert_eq!(to_half(-2.0), 0xc000);
+        assert_eq!(to_half(0.333_333), 0x3555);
+        assert_eq!(to_half(65504.0), 0x7bff);
+        assert_eq!(to_half(65520.0), 0x7c00);
+        assert_eq!(to_half(1e6), 0x7c00);
+        assert_eq!(to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(to_half(2f32.powi(-24)), 0x0001);
+        assert_eq!(to_half(2f32.powi(-14)), 0x0400);
+        assert_eq!(to_half(1e-9), 0x0000);
+        assert!(from_half(to_half(f32::NAN)).is_nan());
+
+        for value in [0.1f32, 3.75, 1234.5, -0.0078125, 6e-5] {
+            assert!((from_half(to_half(value)) - value).abs() <= value.abs() / 1024.0);
+        }
+    }
+
+    #[test]
+    fn run_length_encoding_round_trips() {
+        let data: Vec<u8> = [
+            vec![7; 300],
+            (0..200).map(|i| i as u8).collect(),
+            vec![1, 1, 2, 2, 2],
+        ]
+        .concat();
+
+        assert_eq!(run_length_decode(&run_length_encode(&data)), data);
+        assert_eq!(unpredict(&predict(&data)), data);
+    }
+
+    #[test]
+    fn writing_the_header() {
+        let mut out = vec![];
+        Canvas::new(4, 3)
+            .write_exr(&mut out, ExrPixelType::Half, ExrCompression::Zip)
+            .unwrap();
+        let parsed = parse(&out, ExrCompression::Zip, 4 * 3 * 3 * 2);
+
+        let names: Vec<&str> = parsed.attributes.iter().map(|a| a.0.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "channels",
+                "compression",
+                "dataWindow",
+                "displayWindow",
+                "lineOrder",
+                "pixelAspectRatio",
+                "screenWindowCenter",
+                "screenWindowWidth"
+            ]
+        );
+
+        let channels = &parsed.attributes[0].2;
+        assert_eq!(&channels[..6], b"B\0\x01\0\0\0");
+        assert_eq!(parsed.attributes[1].2, vec![3]);
+        assert_eq!(
+            parsed.attributes[2].2,
+            [0i32, 0, 3, 2]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect::<Vec<_>>()
+        );
+        // all three lines fit in one ZIP chunk
+        assert_eq!(parsed.chunks.len(), 1);
+    }
+
+    #[test]
+    fn writing_layers_with_every_compression() {
+        let (width, height) = (5, 3);
+        let mut beauty = Canvas::new(width, height);
+        let mut normal = Canvas::new(width, height);
+        for (x, y, color) in beauty.enumerate_pixels_mut() {
+            *color = Color::new(x as f64 * 10.0, y as f64, 0.5);
+        }
+        normal.fill(Color::new(0.0, 1.0, 0.0));
+
+        let mut frame = FrameBuffer::from_canvas(beauty);
+        frame.add_color_layer("normal", normal);
+        frame.add_scalar_layer("Z", (0..width * height).map(|i| i as f64).collect());
+
+        // channel names sorted: B G R Z normal.B normal.G normal.R
+        let sorted: Vec<Box<dyn Fn(usize, usize) -> f32>> = vec![
+            Box::new(|_, _| 0.5),
+            Box::new(|_, y| y as f32),
+            Box::new(|x, _| x as f32 * 10.0),
+            Box::new(|x, y| (y * width + x) as f32),
+            Box::new(|_, _| 0.0),
+            Box::new(|_, _| 1.0),
+            Box::new(|_, _| 0.0),
+        ];
+
+        for compression in [
+            ExrCompression::None,
+            ExrCompression::Rle,
+            ExrCompression::Zips,
+            ExrCompression::Zip,
+        ] {
+            for pixel_type in [ExrPixelType::Half, ExrPixelType::Float] {
+                let mut out = vec![];
+                frame.write_exr(&mut out, pixel_type, compression).unwrap();
+
+                let lines = compression.lines_per_chunk();
+                let size = pixel_type.size();
+                let parsed = parse(&out, compression, lines * width * sorted.len() * size);
+                assert_eq!(parsed.chunks.len(), height.div_ceil(lines));
+
+                for (first_line, raw) in parsed.chunks {
+                    let mut samples = raw.chunks_exact(size).map(|b| match pixel_type {
+                        ExrPixelType::Half => from_half(u16::from_le_bytes([b[0], b[1]])),
+                        ExrPixelType::Float => f32::from_le_bytes(b.try_into().unwrap()),
+                    });
+
+                    for y in first_line as usize..(first_line as usize + lines).min(height) {
+                        for expected in &sorted {
+                            for x in 0..width {
+                                assert_eq!(samples.next(), Some(expected(x, y)));
+                            }
+                        }
+                    }
+                    assert_eq!(samples.next(), None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writing_premultiplied_alpha() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.fill(Color::new(1.0, 0.5, 0.25));
+        canvas.write_alpha(1, 0, 0.5);
+
+        let mut out = vec![];
+        canvas
+            .write_exr(&mut out, ExrPixelType::Float, ExrCompression::None)
+            .unwrap();
+        let parsed = parse(&out, ExrCompression::None, 2 * 4 * 4);
+
+        let samples: Vec<f32> = parsed.chunks[0]
+            .1
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+            .collect();
+        // channels A, B, G, R
+        assert_eq!(samples, vec![1.0, 0.5, 0.25, 0.125, 0.5, 0.25, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn rejecting_duplicate_or_missing_channels() {
+        let mut frame = FrameBuffer::new(1, 1);
+        let err = frame
+            .write_exr(vec![], ExrPixelType::Half, ExrCompression::None)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        frame.add_color_layer("", Canvas::new(1, 1));
+        frame.add_scalar_layer("R", vec![0.0]);
+        let err = frame
+            .write_exr(vec![], ExrPixelType::Half, ExrCompression::None)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "duplicate EXR channel \"R\"");
+    }
+
+    #[test]
+    fn rejecting_empty_images() {
+        for (width, height) in [(0, 0), (0, 3), (3, 0)] {
+            let err = Canvas::new(width, height)
+                .write_exr(vec![], ExrPixelType::Half, ExrCompression::None)
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(err.to_string(), "an EXR file needs at least one pixel");
+        }
+    }
+}
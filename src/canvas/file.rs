@@ -0,0 +1,438 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::bmp::{BmpError, BmpFormat};
+use super::exr::{self, ExrCompression, ExrPixelType};
+use super::hdr::HdrError;
+use super::pfm::PfmError;
+use super::png::{self, PngError, PngFormat};
+use super::ppm::{PpmError, PpmFormat};
+use super::tga::{self, TgaCompression, TgaError, TgaFormat};
+use super::Canvas;
+use crate::color::transfer::Transfer;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Hdr,
+    Pfm,
+    Bmp,
+    Tga,
+    Exr,
+}
+
+impl ImageFormat {
+    /// Guesses the format from a file extension, ignoring case.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let extension = path.extension().and_then(OsStr::to_str)?;
+
+        match extension.to_ascii_lowercase().as_str() {
+            "ppm" | "pnm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "hdr" | "pic" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            "bmp" | "dib" => Some(ImageFormat::Bmp),
+            "tga" => Some(ImageFormat::Tga),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+
+    /// Recognizes a format from the start of a file. TGA has no magic
+    /// number, so only files ending in the TGA 2.0 footer are detected.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [b'P', b'1'..=b'6', ..] => Some(ImageFormat::Ppm),
+            [b'P', b'F' | b'f', ..] => Some(ImageFormat::Pfm),
+            [b'#', b'?', ..] => Some(ImageFormat::Hdr),
+            [b'B', b'M', ..] => Some(ImageFormat::Bmp),
+            _ if bytes.starts_with(&png::SIGNATURE) => Some(ImageFormat::Png),
+            _ if bytes.starts_with(&exr::MAGIC) => Some(ImageFormat::Exr),
+            _ if bytes.ends_with(tga::FOOTER) => Some(ImageFormat::Tga),
+            _ => None,
+        }
+    }
+
+    /// The transfer function images of this format are assumed to use:
+    /// sRGB for the 8-bit display formats, linear for the float ones.
+    pub fn default_transfer(&self) -> Transfer {
+        match self {
+            ImageFormat::Hdr | ImageFormat::Pfm | ImageFormat::Exr => Transfer::Linear,
+            _ => Transfer::Srgb,
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Ppm => "PPM",
+            ImageFormat::Png => "PNG",
+            ImageFormat::Hdr => "Radiance HDR",
+            ImageFormat::Pfm => "PFM",
+            ImageFormat::Bmp => "BMP",
+            ImageFormat::Tga => "TGA",
+            ImageFormat::Exr => "OpenEXR",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// Neither the contents nor the extension identify a known format.
+    UnknownFormat(PathBuf),
+    /// The format is recognized but can only be written, not read.
+    CannotRead(ImageFormat),
+    Ppm(PpmError),
+    Png(PngError),
+    Hdr(HdrError),
+    Pfm(PfmError),
+    Bmp(BmpError),
+    Tga(TgaError),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "{}", err),
+            ImageError::UnknownFormat(path) => {
+                write!(f, "cannot tell the image format of {}", path.display())
+            }
+            ImageError::CannotRead(format) => {
+                write!(f, "reading {} files is not supported", format)
+            }
+            ImageError::Ppm(err) => write!(f, "{}", err),
+            ImageError::Png(err) => write!(f, "{}", err),
+            ImageError::Hdr(err) => write!(f, "{}", err),
+            ImageError::Pfm(err) => write!(f, "{}", err),
+            ImageError::Bmp(err) => write!(f, "{}", err),
+            ImageError::Tga(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Io(err) => Some(err),
+            ImageError::Ppm(err) => Some(err),
+            ImageError::Png(err) => Some(err),
+            ImageError::Hdr(err) => Some(err),
+            ImageError::Pfm(err) => Some(err),
+            ImageError::Bmp(err) => Some(err),
+            ImageError::Tga(err) => Some(err),
+            ImageError::UnknownFormat(_) | ImageError::CannotRead(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
+
+impl From<PpmError> for ImageError {
+    fn from(err: PpmError) -> Self {
+        ImageError::Ppm(err)
+    }
+}
+
+impl From<PngError> for ImageError {
+    fn from(err: PngError) -> Self {
+        ImageError::Png(err)
+    }
+}
+
+impl From<HdrError> for ImageError {
+    fn from(err: HdrError) -> Self {
+        ImageError::Hdr(err)
+    }
+}
+
+impl From<PfmError> for ImageError {
+    fn from(err: PfmError) -> Self {
+        ImageError::Pfm(err)
+    }
+}
+
+impl From<BmpError> for ImageError {
+    fn from(err: BmpError) -> Self {
+        ImageError::Bmp(err)
+    }
+}
+
+impl From<TgaError> for ImageError {
+    fn from(err: TgaError) -> Self {
+        ImageError::Tga(err)
+    }
+}
+
+impl Canvas {
+    /// Writes the canvas in the format named by the file extension, using
+    /// each format's most widely supported variant: binary 8-bit PPM, 8-bit
+    /// RGB PNG, 24-bit BMP, RLE TGA and ZIP-compressed half-float EXR.
+    /// Canvases with an alpha plane are written with alpha where the format
+    /// supports it.
+    ///
+    /// The canvas holds linear light, which is encoded with the format's
+    /// `default_transfer` on the way out.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ImageError> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_extension(path)
+            .ok_or_else(|| ImageError::UnknownFormat(path.to_path_buf()))?;
+
+        self.save_as(path, format)
+    }
+
+    /// Writes the canvas in `format` regardless of the file extension.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> Result<(), ImageError> {
+        self.export(path, format, format.default_transfer())
+    }
+
+    /// Writes the canvas in `format`, encoding it with `transfer` instead
+    /// of the format's default, e.g. Rec.709 for video or linear for data.
+    pub fn export<P: AsRef<Path>>(
+        &self,
+        path: P,
+        format: ImageFormat,
+        transfer: Transfer,
+    ) -> Result<(), ImageError> {
+        let file = File::create(path)?;
+        let encoded = self.map(|color| color.encode(transfer));
+        let alpha = self.alpha.is_some();
+
+        match format {
+            ImageFormat::Ppm => encoded.write_ppm(file, PpmFormat::Binary, 255)?,
+            ImageFormat::Png if alpha => encoded.write_png(file, PngFormat::Rgba8)?,
+            ImageFormat::Png => encoded.write_png(file, PngFormat::Rgb8)?,
+            ImageFormat::Hdr => encoded.write_hdr(file)?,
+            ImageFormat::Pfm => encoded.write_pfm(file)?,
+            ImageFormat::Bmp if alpha => encoded.write_bmp(file, BmpFormat::Rgba32)?,
+            ImageFormat::Bmp => encoded.write_bmp(file, BmpFormat::Rgb24)?,
+            ImageFormat::Tga if alpha => {
+                encoded.write_tga(file, TgaFormat::Rgba32, TgaCompression::Rle)?
+            }
+            ImageFormat::Tga => encoded.write_tga(file, TgaFormat::Rgb24, TgaCompression::Rle)?,
+            ImageFormat::Exr => encoded.write_exr(file, ExrPixelType::Half, ExrCompression::Zip)?,
+        }
+
+        Ok(())
+    }
+
+    /// Reads an image in any supported format and converts it to linear
+    /// light using the format's `default_transfer`, ready to be used as a
+    /// texture.
+    ///
+    /// The contents decide the format; the extension is only consulted when
+    /// they don't, which in practice means TGA files without a footer.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Canvas, ImageError> {
+        let (canvas, format) = Canvas::read_image(path.as_ref())?;
+        Ok(canvas.map(|color| color.decode(format.default_transfer())))
+    }
+
+    /// Reads an image and decodes it with `transfer` regardless of its
+    /// format. Pass `Transfer::Linear` for data such as normal maps that
+    /// must not be linearized.
+    pub fn open_with<P: AsRef<Path>>(path: P, transfer: Transfer) -> Result<Canvas, ImageError> {
+        let (canvas, _) = Canvas::read_image(path.as_ref())?;
+        Ok(canvas.map(|color| color.decode(transfer)))
+    }
+
+    fn read_image(path: &Path) -> Result<(Canvas, ImageFormat), ImageError> {
+        let bytes = fs::read(path)?;
+        let format = ImageFormat::detect(&bytes)
+            .or_else(|| ImageFormat::from_extension(path))
+            .ok_or_else(|| ImageError::UnknownFormat(path.to_path_buf()))?;
+
+        let canvas = match format {
+            ImageFormat::Ppm => Canvas::from_ppm(&bytes[..])?,
+            ImageFormat::Png => Canvas::from_png(&bytes[..])?,
+            ImageFormat::Hdr => Canvas::from_hdr(&bytes[..])?,
+            ImageFormat::Pfm => Canvas::from_pfm(&bytes[..])?,
+            ImageFormat::Bmp => Canvas::from_bmp(&bytes[..])?,
+            ImageFormat::Tga => Canvas::from_tga(&bytes[..])?,
+            ImageFormat::Exr => return Err(ImageError::CannotRead(format)),
+        };
+
+        Ok((canvas, format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    /// A path in the system temp directory that is removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let name = format!("ray_tracer_{}_{}", std::process::id(), name);
+            TempFile(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn sample_canvas() -> Canvas {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.write_pixel(0, 0, Color::new(1.0, 0.0, 0.0));
+        canvas.write_pixel(2, 1, Color::new(0.0, 0.0, 1.0));
+        canvas
+    }
+
+    #[test]
+    fn detecting_formats_from_magic_bytes() {
+        let canvas = sample_canvas();
+        let mut tga = vec![];
+        canvas
+            .write_tga(&mut tga, TgaFormat::Rgb24, TgaCompression::None)
+            .unwrap();
+
+        assert_eq!(
+            ImageFormat::detect(b"P3\n1 1\n255\n"),
+            Some(ImageFormat::Ppm)
+        );
+        assert_eq!(
+            ImageFormat::detect(b"Pf\n1 1\n-1\n"),
+            Some(ImageFormat::Pfm)
+        );
+        assert_eq!(ImageFormat::detect(b"#?RADIANCE\n"), Some(ImageFormat::Hdr));
+        assert_eq!(ImageFormat::detect(b"BM"), Some(ImageFormat::Bmp));
+        assert_eq!(ImageFormat::detect(&png::SIGNATURE), Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::detect(&exr::MAGIC), Some(ImageFormat::Exr));
+        assert_eq!(ImageFormat::detect(&tga), Some(ImageFormat::Tga));
+        assert_eq!(ImageFormat::detect(b"P7"), None);
+        assert_eq!(ImageFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn guessing_formats_from_extensions() {
+        let format = |name: &str| ImageFormat::from_extension(Path::new(name));
+
+        assert_eq!(format("out.PNG"), Some(ImageFormat::Png));
+        assert_eq!(format("renders/sky.hdr"), Some(ImageFormat::Hdr));
+        assert_eq!(format("beauty.exr"), Some(ImageFormat::Exr));
+        assert_eq!(format("scene.jpg"), None);
+        assert_eq!(format("ppm"), None);
+    }
+
+    #[test]
+    fn saving_and_opening_every_readable_format() {
+        let canvas = sample_canvas();
+
+        for name in ["a.ppm", "a.png", "a.hdr", "a.pfm", "a.bmp", "a.tga"] {
+            let file = TempFile::new(name);
+            canvas.save(&file.0).unwrap();
+
+            let opened = Canvas::open(&file.0).unwrap();
+
+            // RGBE keeps only an 8-bit mantissa per channel
+            assert_eq!((opened.width, opened.height), (canvas.width, canvas.height));
+            for (a, b) in opened.pixels().zip(canvas.pixels()) {
+                let error = (a.red - b.red)
+                    .abs()
+                    .max((a.green - b.green).abs())
+                    .max((a.blue - b.blue).abs());
+                assert!(error < 0.01, "{}: {:?} != {:?}", name, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn encoding_display_formats_on_the_way_out() {
+        let mut canvas = Canvas::new(1, 1);
+        canvas.fill(Color::new(0.214, 0.214, 0.214));
+
+        let srgb = TempFile::new("gray.ppm");
+        canvas.save(&srgb.0).unwrap();
+        assert_eq!(fs::read(&srgb.0).unwrap(), b"P6\n1 1\n255\n\x80\x80\x80");
+        assert!((Canvas::open(&srgb.0).unwrap().pixel_at(0, 0).red - 0.214).abs() < 0.002);
+
+        let linear = TempFile::new("gray_linear.ppm");
+        canvas
+            .export(&linear.0, ImageFormat::Ppm, Transfer::Linear)
+            .unwrap();
+        assert_eq!(fs::read(&linear.0).unwrap(), b"P6\n1 1\n255\n\x37\x37\x37");
+        assert_eq!(
+            Canvas::open_with(&linear.0, Transfer::Linear)
+                .unwrap()
+                .pixel_at(0, 0)
+                .red,
+            55.0 / 255.0
+        );
+
+        // float formats keep linear values untouched
+        let pfm = TempFile::new("gray.pfm");
+        canvas.save(&pfm.0).unwrap();
+        assert_eq!(
+            Canvas::open(&pfm.0).unwrap().pixel_at(0, 0).red,
+            0.214f32 as f64
+        );
+    }
+
+    #[test]
+    fn keeping_alpha_in_formats_that_store_it() {
+        let mut canvas = sample_canvas();
+        canvas.write_alpha(1, 0, 0.0);
+        canvas.write_alpha(2, 1, 0.6);
+
+        for name in ["alpha.png", "alpha.bmp", "alpha.tga"] {
+            let file = TempFile::new(name);
+            canvas.save(&file.0).unwrap();
+            let opened = Canvas::open(&file.0).unwrap();
+
+            assert_eq!(opened.alpha_at(0, 0), 1.0, "{}", name);
+            assert_eq!(opened.alpha_at(1, 0), 0.0, "{}", name);
+            assert_eq!(opened.alpha_at(2, 1), 153.0 / 255.0, "{}", name);
+        }
+
+        let file = TempFile::new("alpha.ppm");
+        canvas.save(&file.0).unwrap();
+        assert_eq!(Canvas::open(&file.0).unwrap().alpha, None);
+    }
+
+    #[test]
+    fn opening_ignores_misleading_extensions() {
+        let canvas = sample_canvas();
+        let file = TempFile::new("really_a_png.bmp");
+        canvas.save_as(&file.0, ImageFormat::Png).unwrap();
+
+        assert_eq!(Canvas::open(&file.0).unwrap(), canvas);
+    }
+
+    #[test]
+    fn reporting_unusable_files() {
+        let canvas = sample_canvas();
+
+        let unknown = TempFile::new("image.xyz");
+        let err = canvas.save(&unknown.0).unwrap_err();
+        assert!(matches!(err, ImageError::UnknownFormat(_)));
+        assert!(!unknown.0.exists());
+
+        let exr = TempFile::new("image.exr");
+        canvas.save(&exr.0).unwrap();
+        let err = Canvas::open(&exr.0).unwrap_err();
+        assert_eq!(err.to_string(), "reading OpenEXR files is not supported");
+
+        let missing = TempFile::new("missing.png");
+        assert!(matches!(Canvas::open(&missing.0), Err(ImageError::Io(_))));
+
+        let corrupt = TempFile::new("corrupt.png");
+        fs::write(&corrupt.0, &png::SIGNATURE[..6]).unwrap();
+        assert!(matches!(Canvas::open(&corrupt.0), Err(ImageError::Png(_))));
+    }
+}
@@ -0,0 +1,494 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::Canvas;
+use crate::color::Color;
+
+/// Scanlines this wide can use the run-length encoding; others are flat.
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+/// Shortest run worth encoding as a run rather than as literals.
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+const MAX_LITERALS: usize = 128;
+
+#[derive(Debug)]
+pub enum HdrError {
+    Io(io::Error),
+    NotHdr,
+    UnsupportedFormat(String),
+    InvalidResolution(String),
+    Truncated,
+    /// A run-length encoded scanline overflows or underfills the row.
+    BadScanline(usize),
+}
+
+impl fmt::Display for HdrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdrError::Io(err) => write!(f, "failed to read image: {}", err),
+            HdrError::NotHdr => write!(f, "not a Radiance HDR file"),
+            HdrError::UnsupportedFormat(format) => {
+                write!(f, "unsupported HDR pixel format {:?}", format)
+            }
+            HdrError::InvalidResolution(line) => {
+                write!(f, "unsupported HDR resolution line {:?}", line)
+            }
+            HdrError::Truncated => write!(f, "HDR file is truncated"),
+            HdrError::BadScanline(y) => write!(f, "corrupt run-length data in scanline {}", y),
+        }
+    }
+}
+
+impl Error for HdrError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HdrError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HdrError {
+    fn from(err: io::Error) -> Self {
+        HdrError::Io(err)
+    }
+}
+
+/// Packs a color into a shared-exponent RGBE quadruple. Negative channels
+/// are clamped to zero.
+fn to_rgbe(color: &Color) -> [u8; 4] {
+    let (r, g, b) = (
+        color.red.max(0.0),
+        color.green.max(0.0),
+        color.blue.max(0.0),
+    );
+    let v = r.max(g).max(b);
+
+    if v < 1e-32 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    let mut m = v / 2f64.powi(e);
+    if m >= 1.0 {
+        m /= 2.0;
+        e += 1;
+    } else if m < 0.5 {
+        m *= 2.0;
+        e -= 1;
+    }
+
+    let scale = m * 256.0 / v;
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+fn from_rgbe(rgbe: &[u8]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        (rgbe[0] as f64 + 0.5) * f,
+        (rgbe[1] as f64 + 0.5) * f,
+        (rgbe[2] as f64 + 0.5) * f,
+    )
+}
+
+/// Run-length encodes one component of a scanline: a byte above 128 is a
+/// run of `byte - 128` copies of the next byte, otherwise it counts the
+/// literal bytes that follow.
+fn write_rle_component<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let run = bytes[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&b| b == bytes[i])
+            .count();
+
+        if run >= MIN_RUN {
+            out.write_all(&[128 + run as u8, bytes[i]])?;
+            i += run;
+            continue;
+        }
+
+        // Gather literals up to the start of the next worthwhile run.
+        let start = i;
+        while i < bytes.len() && i - start < MAX_LITERALS {
+            let run = bytes[i..]
+                .iter()
+                .take(MIN_RUN)
+                .take_while(|&&b| b == bytes[i])
+                .count();
+            if run >= MIN_RUN {
+                break;
+            }
+            i += 1;
+        }
+
+        out.write_all(&[(i - start) as u8])?;
+        out.write_all(&bytes[start..i])?;
+    }
+
+    Ok(())
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], HdrError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos + len)
+            .ok_or(HdrError::Truncated)?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+
+    fn line(&mut self) -> Result<&'a str, HdrError> {
+        let rest = &self.bytes[self.pos..];
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(HdrError::Truncated)?;
+        self.pos += end + 1;
+
+        std::str::from_utf8(&rest[..end]).map_err(|_| HdrError::NotHdr)
+    }
+}
+
+fn read_rle_scanline(cursor: &mut Cursor, width: usize, y: usize) -> Result<Vec<Color>, HdrError> {
+    let mut components = vec![0u8; width * 4];
+
+    for component in 0..4 {
+        let mut x = 0;
+
+        while x < width {
+            let count = cursor.take(1)?[0] as usize;
+
+            if count > 128 {
+                let count = count - 128;
+                let value = cursor.take(1)?[0];
+                if x + count > width {
+                    return Err(HdrError::BadScanline(y));
+                }
+                for i in x..x + count {
+                    components[i * 4 + component] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(HdrError::BadScanline(y));
+                }
+                for (i, &value) in cursor.take(count)?.iter().enumerate() {
+                    components[(x + i) * 4 + component] = value;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(components.chunks_exact(4).map(from_rgbe).collect())
+}
+
+/// Reads an uncompressed scanline, honouring the old-style `1 1 1 n`
+/// repeat markers that copy the previous pixel.
+fn read_flat_scanline(cursor: &mut Cursor, width: usize, y: usize) -> Result<Vec<Color>, HdrError> {
+    let mut pixels: Vec<Color> = Vec::with_capacity(width);
+    let mut shift = 0;
+
+    while pixels.len() < width {
+        let rgbe = cursor.take(4)?;
+
+        if rgbe[..3] == [1, 1, 1] {
+            let previous = *pixels.last().ok_or(HdrError::BadScanline(y))?;
+            // each further marker in a row adds 8 more bits to the count
+            if shift >= usize::BITS {
+                return Err(HdrError::BadScanline(y));
+            }
+            let count = (rgbe[3] as usize) << shift;
+            if count > width - pixels.len() {
+                return Err(HdrError::BadScanline(y));
+            }
+            pixels.extend(std::iter::repeat_n(previous, count));
+            shift += 8;
+        } else {
+            pixels.push(from_rgbe(rgbe));
+            shift = 0;
+        }
+    }
+
+    Ok(pixels)
+}
+
+impl Canvas {
+    /// Writes a Radiance `.hdr` file with run-length encoded RGBE pixels,
+    /// keeping values above 1.0 instead of clamping them.
+    pub fn write_hdr<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut out = BufWriter::new(writer);
+
+        write!(
+            out,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+
+        let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&self.width);
+        let mut components = vec![0u8; self.width];
+
+        for row in self.rows().take(self.height) {
+            let rgbe: Vec<[u8; 4]> = row.iter().map(to_rgbe).collect();
+
+            if !rle {
+                out.write_all(rgbe.as_flattened())?;
+                continue;
+            }
+
+            out.write_all(&[2, 2, (self.width >> 8) as u8, self.width as u8])?;
+            for component in 0..4 {
+                for (byte, pixel) in components.iter_mut().zip(&rgbe) {
+                    *byte = pixel[component];
+                }
+                write_rle_component(&mut out, &components)?;
+            }
+        }
+
+        out.flush()
+    }
+
+    /// Reads a Radiance `.hdr` file in either the run-length encoded or
+    /// flat RGBE layout. Only `+X` scanlines are supported, top-down (`-Y`)
+    /// or bottom-up (`+Y`).
+    pub fn from_hdr<R: Read>(mut reader: R) -> Result<Canvas, HdrError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let mut cursor = Cursor {
+            bytes: &bytes,
+            pos: 0,
+        };
+
+        if !bytes.starts_with(b"#?") {
+            return Err(HdrError::NotHdr);
+        }
+
+        loop {
+            let line = cursor.line()?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(HdrError::UnsupportedFormat(format.to_string()));
+                }
+            }
+        }
+
+        let resolution = cursor.line()?;
+        let invalid = || HdrError::InvalidResolution(resolution.to_string());
+        let (flip, width, height) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            [y_axis @ ("-Y" | "+Y"), height, "+X", width] => (
+                y_axis == "+Y",
+                width.parse().map_err(|_| invalid())?,
+                height.parse().map_err(|_| invalid())?,
+            ),
+            _ => return Err(invalid()),
+        };
+
+        // check the data can cover the image before allocating it: a run
+        // length encoded scanline takes at least 4 bytes, a flat pixel 4
+        let rle_width = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+        let pixels = width.checked_mul(height).ok_or_else(invalid)?;
+        let minimum = if rle_width { height } else { pixels };
+        if minimum.checked_mul(4).ok_or_else(invalid)? > bytes.len() - cursor.pos {
+            return Err(invalid());
+        }
+
+        let mut canvas = Canvas::new(width, height);
+
+        for y in 0..height {
+            let marker = bytes.get(cursor.pos..cursor.pos + 4);
+            let pixels = match marker {
+                Some(&[2, 2, hi, lo]) if rle_width && hi & 0x80 == 0 => {
+                    if (hi as usize) << 8 | lo as usize != width {
+                        return Err(HdrError::BadScanline(y));
+                    }
+                    cursor.pos += 4;
+                    read_rle_scanline(&mut cursor, width, y)?
+                }
+                _ => read_flat_scanline(&mut cursor, width, y)?,
+            };
+
+            let row = if flip { height - 1 - y } else { y };
+            canvas.row_mut(row).copy_from_slice(&pixels);
+        }
+
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &Color, b: &Color) {
+        // RGBE keeps 8 bits of mantissa relative to the brightest channel.
+        let tolerance = a.red.max(a.green).max(a.blue) / 128.0;
+
+        assert!((a.red - b.red).abs() <= tolerance, "{:?} != {:?}", a, b);
+        assert!((a.green - b.green).abs() <= tolerance, "{:?} != {:?}", a, b);
+        assert!((a.blue - b.blue).abs() <= tolerance, "{:?} != {:?}", a, b);
+    }
+
+    fn bright_canvas(width: usize, height: usize) -> Canvas {
+        let mut c = Canvas::new(width, height);
+
+        for (x, y, color) in c.enumerate_pixels_mut() {
+            if x % 5 != 0 {
+                *color = Color::new(
+                    (x * 13) as f64 / 7.0,
+                    0.001 * y as f64,
+                    if y % 2 == 0 { 250.0 } else { 0.25 },
+                );
+            }
+        }
+        c
+    }
+
+    #[test]
+    fn rgbe_conversion() {
+        assert_eq!(to_rgbe(&Color::new(1.0, 0.5, 0.0)), [128, 64, 0, 129]);
+        assert_eq!(to_rgbe(&Color::new(0.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(&Color::new(-3.0, 0.0, 0.0)), [0, 0, 0, 0]);
+        assert_eq!(from_rgbe(&[0, 0, 0, 0]), Color::new(0.0, 0.0, 0.0));
+        assert_close(
+            &from_rgbe(&to_rgbe(&Color::new(1000.0, 3.5, 0.01))),
+            &Color::new(1000.0, 3.5, 0.01),
+        );
+    }
+
+    #[test]
+    fn round_tripping_run_length_encoded_scanlines() {
+        let original = bright_canvas(40, 6);
+        let mut out = vec![];
+        original.write_hdr(&mut out).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 6 +X 40\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..header.len() + 4], &[2, 2, 0, 40]);
+
+        let decoded = Canvas::from_hdr(&out[..]).unwrap();
+        for (a, b) in original.pixels().zip(decoded.pixels()) {
+            assert_close(a, b);
+        }
+    }
+
+    #[test]
+    fn flat_and_run_length_files_decode_the_same() {
+        let mut flat = Canvas::new(5, 3);
+        flat.fill(Color::new(12.5, 0.125, 3.0));
+        let mut out = vec![];
+        flat.write_hdr(&mut out).unwrap();
+
+        let decoded = Canvas::from_hdr(&out[..]).unwrap();
+        for pixel in decoded.pixels() {
+            assert_close(pixel, &Color::new(12.5, 0.125, 3.0));
+        }
+    }
+
+    #[test]
+    fn runs_compress_flat_rows() {
+        let mut c = Canvas::new(1000, 10);
+        c.fill(Color::new(2.0, 2.0, 2.0));
+        let mut out = vec![];
+        c.write_hdr(&mut out).unwrap();
+
+        assert!(out.len() < 1000 * 10 / 10);
+    }
+
+    #[test]
+    fn reading_bottom_up_files_with_old_style_runs() {
+        let mut file = b"#?RGBE\n# comment\n\n+Y 2 +X 3\n".to_vec();
+        file.extend([128, 0, 0, 129, 1, 1, 1, 2]);
+        file.extend([0, 128, 0, 129, 0, 0, 128, 129, 0, 0, 128, 129]);
+        // flat files must hold 4 bytes per pixel before the runs are read
+        file.extend([0; 4]);
+
+        let c = Canvas::from_hdr(&file[..]).unwrap();
+        let red = Color::new(128.5 / 128.0, 0.5 / 128.0, 0.5 / 128.0);
+
+        assert_eq!(c.row(1), &[red, red, red]);
+        assert_eq!(c.pixel_at(0, 0).green, 128.5 / 128.0);
+        assert_eq!(c.pixel_at(2, 0).blue, 128.5 / 128.0);
+    }
+
+    #[test]
+    fn rejecting_invalid_files() {
+        let message = |file: &[u8]| Canvas::from_hdr(file).unwrap_err().to_string();
+
+        assert_eq!(message(b"P6 1 1 255\n"), "not a Radiance HDR file");
+        assert_eq!(
+            message(b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 1 +X 1\n"),
+            "unsupported HDR pixel format \"32-bit_rle_xyze\""
+        );
+        assert_eq!(
+            message(b"#?RADIANCE\n\n+X 1 -Y 1\n"),
+            "unsupported HDR resolution line \"+X 1 -Y 1\""
+        );
+        assert_eq!(
+            message(b"#?RADIANCE\n\n-Y 1 +X 8\n\x02\x02\x00\x08\x88\x00"),
+            "HDR file is truncated"
+        );
+
+        let mut bad_run = b"#?RADIANCE\n\n-Y 1 +X 8\n\x02\x02\x00\x08".to_vec();
+        bad_run.extend([137, 0]);
+        assert_eq!(message(&bad_run), "corrupt run-length data in scanline 0");
+    }
+
+    #[test]
+    fn rejecting_resolutions_the_data_cannot_cover() {
+        let message = |file: &[u8]| Canvas::from_hdr(file).unwrap_err().to_string();
+
+        assert_eq!(
+            message(b"#?RADIANCE\n\n-Y 4000000 +X 4000000\n\x80\x00\x00\x81"),
+            "unsupported HDR resolution line \"-Y 4000000 +X 4000000\""
+        );
+        assert_eq!(
+            message(b"#?RADIANCE\n\n-Y 1 +X 2\n\x80\x00\x00\x81"),
+            "unsupported HDR resolution line \"-Y 1 +X 2\""
+        );
+
+        let huge = format!("#?RADIANCE\n\n-Y {} +X {}\n", usize::MAX, 3);
+        assert!(matches!(
+            Canvas::from_hdr(huge.as_bytes()),
+            Err(HdrError::InvalidResolution(_))
+        ));
+    }
+
+    #[test]
+    fn rejecting_endless_old_style_runs() {
+        let mut file = b"#?RADIANCE\n\n-Y 1 +X 2\n".to_vec();
+        file.extend([128, 0, 0, 129]);
+        for _ in 0..9 {
+            file.extend([1, 1, 1, 0]);
+        }
+
+        assert!(matches!(
+            Canvas::from_hdr(&file[..]),
+            Err(HdrError::BadScanline(0))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::Canvas;
+use crate::color::Color;
+
+#[derive(Debug)]
+pub enum PfmError {
+    Io(io::Error),
+    NotPfm,
+    InvalidHeader(String),
+    Truncated,
+}
+
+impl fmt::Display for PfmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PfmError::Io(err) => write!(f, "failed to read image: {}", err),
+            PfmError::NotPfm => write!(f, "not a PFM file"),
+            PfmError::InvalidHeader(field) => write!(f, "invalid PFM header field {:?}", field),
+            PfmError::Truncated => write!(f, "PFM file is truncated"),
+        }
+    }
+}
+
+impl Error for PfmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PfmError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PfmError {
+    fn from(err: io::Error) -> Self {
+        PfmError::Io(err)
+    }
+}
+
+impl Canvas {
+    /// Writes a Portable FloatMap: little-endian 32-bit floats per channel,
+    /// rows stored bottom to top, with no clamping.
+    pub fn write_pfm<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut out = BufWriter::new(writer);
+
+        // A negative scale marks the samples as little-endian.
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        for y in (0..self.height).rev() {
+            for color in self.row(y) {
+                for sample in [color.red, color.green, color.blue] {
+                    out.write_all(&(sample as f32).to_le_bytes())?;
+                }
+            }
+        }
+
+        out.flush()
+    }
+
+    /// Reads a color (`PF`) or grayscale (`Pf`) Portable FloatMap of either
+    /// byte order. The magnitude of the scale factor is ignored.
+    pub fn from_pfm<R: Read>(mut reader: R) -> Result<Canvas, PfmError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let channels = match bytes.get(..2) {
+            Some(b"PF") => 3,
+            Some(b"Pf") => 1,
+            _ => return Err(PfmError::NotPfm),
+        };
+
+        // The header is three whitespace separated fields after the magic,
+        // followed by exactly one whitespace byte.
+        let mut pos = 2;
+        let mut fields = vec![];
+        while fields.len() < 3 {
+            while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            let start = pos;
+            while bytes.get(pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(PfmError::Truncated);
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        pos += 1;
+
+        let invalid = |field: &String| PfmError::InvalidHeader(field.clone());
+        let width: usize = fields[0].parse().map_err(|_| invalid(&fields[0]))?;
+        let height: usize = fields[1].parse().map_err(|_| invalid(&fields[1]))?;
+        let scale: f64 = fields[2].parse().map_err(|_| invalid(&fields[2]))?;
+        if scale == 0.0 || !scale.is_finite() {
+            return Err(invalid(&fields[2]));
+        }
+
+        // a raster too large to address can't be in the file either
+        let raster = width
+            .checked_mul(height)
+            .and_then(|pixels| pixels.checked_mul(channels * 4))
+            .and_then(|len| bytes.get(pos..pos.checked_add(len)?))
+            .ok_or(PfmError::Truncated)?;
+        let samples: Vec<f64> = raster
+            .chunks_exact(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                if scale < 0.0 {
+                    f32::from_le_bytes(b) as f64
+                } else {
+                    f32::from_be_bytes(b) as f64
+                }
+            })
+            .collect();
+
+        let mut canvas = Canvas::new(width, height);
+        for (i, sample) in samples.chunks_exact(channels).enumerate() {
+            let (x, y) = (i % width, height - 1 - i / width);
+            let color = match sample {
+                &[gray] => Color::new(gray, gray, gray),
+                _ => Color::new(sample[0], sample[1], sample[2]),
+            };
+            canvas.write_pixel(x, y, color);
+        }
+
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_pfm_bottom_row_first() {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 1, Color::new(1.5, -2.0, 1000.0));
+
+        let mut out = vec![];
+        c.write_pfm(&mut out).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(out.len(), header.len() + 2 * 2 * 3 * 4);
+        assert_eq!(&out[header.len()..header.len() + 4], &1.5f32.to_le_bytes());
+        assert_eq!(
+            &out[header.len() + 8..header.len() + 12],
+            &1000.0f32.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn round_tripping_unclamped_values() {
+        let mut c = Canvas::new(3, 2);
+        for (x, y, color) in c.enumerate_pixels_mut() {
+            *color = Color::new(x as f64 * 100.5, -(y as f64), 0.25 + x as f64);
+        }
+
+        let mut out = vec![];
+        c.write_pfm(&mut out).unwrap();
+
+        assert_eq!(Canvas::from_pfm(&out[..]).unwrap(), c);
+    }
+
+    #[test]
+    fn reading_big_endian_grayscale() {
+        let mut file = b"Pf 2 1\n1.0\n".to_vec();
+        file.extend(0.5f32.to_be_bytes());
+        file.extend(8.0f32.to_be_bytes());
+
+        let c = Canvas::from_pfm(&file[..]).unwrap();
+
+        assert_eq!(c.pixel_at(0, 0), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(c.pixel_at(1, 0), Color::new(8.0, 8.0, 8.0));
+    }
+
+    #[test]
+    fn rejecting_invalid_files() {
+        let message = |file: &[u8]| Canvas::from_pfm(file).unwrap_err().to_string();
+
+        assert_eq!(message(b"P6 1 1 255\n"), "not a PFM file");
+        assert_eq!(
+            message(b"PF\n1 x\n-1.0\n"),
+            "invalid PFM header field \"x\""
+        );
+        assert_eq!(message(b"PF\n1 1\n0\n"), "invalid PFM header field \"0\"");
+        assert_eq!(message(b"PF\n1 1\n-1.0\n\0\0"), "PFM file is truncated");
+    }
+
+    #[test]
+    fn rejecting_huge_dimensions() {
+        let message = |file: &[u8]| Canvas::from_pfm(file).unwrap_err().to_string();
+
+        for header in [
+            format!("PF\n{} {}\n-1.0\n", usize::MAX, usize::MAX),
+            format!("Pf\n{} 1\n-1.0\n", usize::MAX / 2),
+        ] {
+            assert_eq!(message(header.as_bytes()), "PFM file is truncated");
+        }
+    }
+}
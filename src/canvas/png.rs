@@ -0,0 +1,646 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::deflate::{zlib_compress, zlib_decompress, InflateError};
+use super::{alpha_plane, quantize, Canvas};
+use crate::color::Color;
+
+pub(super) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+/// The largest width or height the PNG specification allows.
+const MAX_DIMENSION: usize = (1 << 31) - 1;
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+fn crc32(chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffffu32;
+
+    for bytes in chunks {
+        for &byte in *bytes {
+            crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+    }
+
+    crc ^ 0xffffffff
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PngFormat {
+    Rgb8,
+    Rgba8,
+    Rgb16,
+    Rgba16,
+}
+
+impl PngFormat {
+    fn color_type(&self) -> u8 {
+        match self {
+            PngFormat::Rgb8 | PngFormat::Rgb16 => COLOR_RGB,
+            PngFormat::Rgba8 | PngFormat::Rgba16 => COLOR_RGBA,
+        }
+    }
+
+    fn bit_depth(&self) -> u8 {
+        match self {
+            PngFormat::Rgb8 | PngFormat::Rgba8 => 8,
+            PngFormat::Rgb16 | PngFormat::Rgba16 => 16,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PngError {
+    Io(io::Error),
+    NotPng,
+    Truncated,
+    /// A chunk's stored CRC doesn't match its contents.
+    BadCrc([u8; 4]),
+    InvalidHeader(&'static str),
+    Unsupported(&'static str),
+    MissingChunk(&'static str),
+    InvalidFilter(u8),
+    Inflate(InflateError),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Io(err) => write!(f, "failed to read image: {}", err),
+            PngError::NotPng => write!(f, "not a PNG file"),
+            PngError::Truncated => write!(f, "PNG file is truncated"),
+            PngError::BadCrc(chunk) => write!(
+                f,
+                "CRC mismatch in {} chunk",
+                String::from_utf8_lossy(chunk)
+            ),
+            PngError::InvalidHeader(reason) => write!(f, "invalid PNG header: {}", reason),
+            PngError::Unsupported(what) => write!(f, "unsupported PNG feature: {}", what),
+            PngError::MissingChunk(chunk) => write!(f, "PNG is missing its {} chunk", chunk),
+            PngError::InvalidFilter(filter) => write!(f, "invalid PNG filter type {}", filter),
+            PngError::Inflate(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for PngError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PngError::Io(err) => Some(err),
+            PngError::Inflate(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError {
+    fn from(err: io::Error) -> Self {
+        PngError::Io(err)
+    }
+}
+
+impl From<InflateError> for PngError {
+    fn from(err: InflateError) -> Self {
+        PngError::Inflate(err)
+    }
+}
+
+fn write_chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    out.write_all(&crc32(&[kind, data]).to_be_bytes())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Applies filter `kind` to `row` given the unfiltered row above it, where
+/// `bpp` is the number of bytes per complete pixel.
+fn filter_row(kind: u8, row: &[u8], prior: &[u8], bpp: usize, out: &mut Vec<u8>) {
+    out.push(kind);
+
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prior[i];
+        let c = if i >= bpp { prior[i - bpp] } else { 0 };
+
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c),
+        };
+        out.push(row[i].wrapping_sub(predicted));
+    }
+}
+
+/// Undoes the filter on `row` in place.
+fn unfilter_row(kind: u8, row: &mut [u8], prior: &[u8], bpp: usize) -> Result<(), PngError> {
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prior[i];
+        let c = if i >= bpp { prior[i - bpp] } else { 0 };
+
+        let predicted = match kind {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(PngError::InvalidFilter(kind)),
+        };
+        row[i] = row[i].wrapping_add(predicted);
+    }
+
+    Ok(())
+}
+
+/// Picks the filter per row with the smallest sum of absolute differences,
+/// the usual heuristic for getting good compression.
+fn filter_scanlines(raw: &[u8], stride: usize, bpp: usize) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(raw.len() + raw.len() / stride.max(1));
+    let zeros = vec![0; stride];
+    let mut candidate = Vec::with_capacity(stride + 1);
+    let mut best = Vec::with_capacity(stride + 1);
+
+    for (y, row) in raw.chunks_exact(stride.max(1)).enumerate() {
+        let prior = if y == 0 {
+            &zeros[..]
+        } else {
+            &raw[(y - 1) * stride..y * stride]
+        };
+        let mut best_score = u64::MAX;
+
+        for kind in 0..5 {
+            candidate.clear();
+            filter_row(kind, row, prior, bpp, &mut candidate);
+
+            let score = candidate[1..]
+                .iter()
+                .map(|&byte| (byte as i8).unsigned_abs() as u64)
+                .sum();
+            if score < best_score {
+                best_score = score;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+
+        filtered.extend_from_slice(&best);
+    }
+
+    filtered
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self, PngError> {
+        if data.len() != 13 {
+            return Err(PngError::InvalidHeader("IHDR must be 13 bytes"));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]) as usize;
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let (bit_depth, color_type) = (data[8], data[9]);
+
+        let valid_depth = match color_type {
+            COLOR_GRAY => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            COLOR_PALETTE => matches!(bit_depth, 1 | 2 | 4 | 8),
+            COLOR_RGB | COLOR_GRAY_ALPHA | COLOR_RGBA => matches!(bit_depth, 8 | 16),
+            _ => return Err(PngError::InvalidHeader("unknown color type")),
+        };
+
+        if !valid_depth {
+            return Err(PngError::InvalidHeader(
+                "bit depth not allowed for color type",
+            ));
+        }
+        if width == 0 || height == 0 {
+            return Err(PngError::InvalidHeader("image has no pixels"));
+        }
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(PngError::InvalidHeader("dimensions exceed 2^31 - 1"));
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(PngError::InvalidHeader(
+                "unknown compression or filter method",
+            ));
+        }
+        if data[12] != 0 {
+            return Err(PngError::Unsupported("interlaced images"));
+        }
+
+        Ok(Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+        })
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_RGB => 3,
+            COLOR_GRAY_ALPHA => 2,
+            COLOR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes per scanline, without the filter byte; `None` if it doesn't
+    /// fit in memory.
+    fn stride(&self) -> Option<usize> {
+        Some(self.width.checked_mul(self.bits_per_pixel())?.div_ceil(8))
+    }
+}
+
+/// Reads sample `index` of a scanline packed at `bit_depth` bits per sample.
+fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * bit_depth as usize;
+            let shift = 8 - bit_depth as usize - bit % 8;
+            (row[bit / 8] >> shift) as u16 & ((1 << bit_depth) - 1)
+        }
+    }
+}
+
+impl Canvas {
+    /// Encodes the canvas as a PNG. Channels are clamped to `0.0..=1.0`;
+    /// RGBA output takes the canvas's alpha plane, or is fully opaque when
+    /// it has none.
+    pub fn write_png<W: Write>(&self, mut writer: W, format: PngFormat) -> io::Result<()> {
+        let channels = if format.color_type() == COLOR_RGBA {
+            4
+        } else {
+            3
+        };
+        let sample_bytes = format.bit_depth() as usize / 8;
+        let max = ((1u32 << format.bit_depth()) - 1) as f64;
+        let bpp = channels * sample_bytes;
+
+        let mut raw = Vec::with_capacity(self.data.len() * bpp);
+        for (i, color) in self.pixels().enumerate() {
+            let samples = [color.red, color.green, color.blue, self.opacity(i)];
+
+            for &value in &samples[..channels] {
+                let value = quantize(value, max);
+                if sample_bytes == 2 {
+                    raw.extend_from_slice(&value.to_be_bytes());
+                } else {
+                    raw.push(value as u8);
+                }
+            }
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(self.width as u32).to_be_bytes());
+        header.extend_from_slice(&(self.height as u32).to_be_bytes());
+        header.extend_from_slice(&[format.bit_depth(), format.color_type(), 0, 0, 0]);
+
+        let filtered = filter_scanlines(&raw, self.width * bpp, bpp);
+
+        writer.write_all(&SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        write_chunk(&mut writer, b"IDAT", &zlib_compress(&filtered))?;
+        write_chunk(&mut writer, b"IEND", &[])?;
+        writer.flush()
+    }
+
+    /// Decodes a non-interlaced PNG of any color type and bit depth.
+    /// Gray fills all three channels. Alpha becomes the canvas's alpha plane
+    /// unless every pixel is opaque.
+    pub fn from_png<R: Read>(mut reader: R) -> Result<Canvas, PngError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        if !bytes.starts_with(&SIGNATURE) {
+            return Err(PngError::NotPng);
+        }
+
+        let mut pos = SIGNATURE.len();
+        let mut header = None;
+        let mut palette: &[u8] = &[];
+        let mut compressed = vec![];
+
+        loop {
+            let length = bytes.get(pos..pos + 4).ok_or(PngError::Truncated)?;
+            let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+            let kind: [u8; 4] = bytes
+                .get(pos + 4..pos + 8)
+                .ok_or(PngError::Truncated)?
+                .try_into()
+                .unwrap();
+            let end = (pos + 8)
+                .checked_add(length)
+                .filter(|end| end.checked_add(4).is_some_and(|crc| crc <= bytes.len()))
+                .ok_or(PngError::Truncated)?;
+            let data = &bytes[pos + 8..end];
+            let crc = &bytes[end..end + 4];
+
+            if u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]) != crc32(&[&kind, data]) {
+                return Err(PngError::BadCrc(kind));
+            }
+            pos = end + 4;
+
+            match &kind {
+                b"IHDR" => header = Some(Header::parse(data)?),
+                b"PLTE" => palette = data,
+                b"IDAT" => compressed.extend_from_slice(data),
+                b"IEND" => break,
+                // Unknown critical chunks (uppercase first letter) change how
+                // the image must be decoded; ancillary ones can be skipped.
+                _ if kind[0].is_ascii_uppercase() => {
+                    return Err(PngError::Unsupported("unknown critical chunk"))
+                }
+                _ => {}
+            }
+        }
+
+        let header = header.ok_or(PngError::MissingChunk("IHDR"))?;
+        if compressed.is_empty() {
+            return Err(PngError::MissingChunk("IDAT"));
+        }
+        if header.color_type == COLOR_PALETTE && palette.is_empty() {
+            return Err(PngError::MissingChunk("PLTE"));
+        }
+
+        let (stride, size) = header
+            .stride()
+            .and_then(|stride| Some((stride, (stride + 1).checked_mul(header.height)?)))
+            .ok_or(PngError::InvalidHeader("image is too large"))?;
+        let bpp = header.bits_per_pixel().div_ceil(8);
+        let mut scanlines = zlib_decompress(&compressed)?;
+        if scanlines.len() < size {
+            return Err(PngError::Truncated);
+        }
+
+        let mut canvas = Canvas::new(header.width, header.height);
+        let mut alpha = vec![];
+        let max = ((1u32 << header.bit_depth) - 1) as f64;
+        let mut prior = vec![0; stride];
+
+        for y in 0..header.height {
+            let line = &mut scanlines[y * (stride + 1)..(y + 1) * (stride + 1)];
+            let (kind, row) = line.split_first_mut().unwrap();
+            unfilter_row(*kind, row, &prior, bpp)?;
+
+            for (x, color) in canvas.row_mut(y).iter_mut().enumerate() {
+                let first = x * header.channels();
+                let value = |i: usize| sample(row, first + i, header.bit_depth) as f64 / max;
+
+                *color = match header.color_type {
+                    COLOR_GRAY | COLOR_GRAY_ALPHA => Color::new(value(0), value(0), value(0)),
+                    COLOR_PALETTE => {
+                        let entry = sample(row, x, header.bit_depth) as usize * 3;
+                        let rgb = palette
+                            .get(entry..entry + 3)
+                            .ok_or(PngError::InvalidHeader("palette index out of range"))?;
+                        Color::new(
+                            rgb[0] as f64 / 255.0,
+                            rgb[1] as f64 / 255.0,
+                            rgb[2] as f64 / 255.0,
+                        )
+                    }
+                    _ => Color::new(value(0), value(1), value(2)),
+                };
+
+                match header.color_type {
+                    COLOR_GRAY_ALPHA => alpha.push(value(1)),
+                    COLOR_RGBA => alpha.push(value(3)),
+                    _ => {}
+                }
+            }
+
+            prior.copy_from_slice(row);
+        }
+
+        if !alpha.is_empty() {
+            canvas.alpha = alpha_plane(alpha);
+        }
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: usize, height: usize) -> Canvas {
+        let mut c = Canvas::new(width, height);
+
+        for (x, y, color) in c.enumerate_pixels_mut() {
+            *color = Color::new(
+                x as f64 / (width - 1) as f64,
+                y as f64 / (height - 1) as f64,
+                ((x + y) % 3) as f64 / 2.0,
+            );
+        }
+        c
+    }
+
+    fn png(header: [u8; 13], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        write_chunk(&mut out, b"IHDR", &header).unwrap();
+        for (kind, data) in chunks {
+            write_chunk(&mut out, kind, data).unwrap();
+        }
+        write_chunk(&mut out, b"IEND", &[]).unwrap();
+        out
+    }
+
+    #[test]
+    fn crc32_checksum() {
+        assert_eq!(crc32(&[b"123456789"]), 0xcbf43926);
+        assert_eq!(crc32(&[b"IEND"]), 0xae426082);
+    }
+
+    #[test]
+    fn writing_png_structure() {
+        let mut out = vec![];
+        Canvas::new(3, 2)
+            .write_png(&mut out, PngFormat::Rgb8)
+            .unwrap();
+
+        assert_eq!(&out[..8], &SIGNATURE);
+        assert_eq!(&out[8..16], b"\0\0\0\x0dIHDR");
+        assert_eq!(&out[16..29], &[0, 0, 0, 3, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(&out[out.len() - 12..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+    }
+
+    #[test]
+    fn round_tripping_every_format() {
+        let original = gradient(17, 9);
+
+        for (format, max) in [
+            (PngFormat::Rgb8, 255.0),
+            (PngFormat::Rgba8, 255.0),
+            (PngFormat::Rgb16, 65535.0),
+            (PngFormat::Rgba16, 65535.0),
+        ] {
+            let mut out = vec![];
+            original.write_png(&mut out, format).unwrap();
+            let decoded = Canvas::from_png(&out[..]).unwrap();
+
+            assert_eq!((decoded.width, decoded.height), (17, 9));
+            for (a, b) in original.pixels().zip(decoded.pixels()) {
+                assert!((a.red - b.red).abs() <= 0.5 / max);
+                assert!((a.green - b.green).abs() <= 0.5 / max);
+                assert!((a.blue - b.blue).abs() <= 0.5 / max);
+            }
+        }
+    }
+
+    #[test]
+    fn flat_images_compress_well() {
+        let mut c = Canvas::new(256, 256);
+        c.fill(Color::new(0.2, 0.4, 0.6));
+
+        let mut out = vec![];
+        c.write_png(&mut out, PngFormat::Rgb8).unwrap();
+
+        assert!(out.len() < 1024);
+    }
+
+    #[test]
+    fn decoding_gray_palette_and_packed_samples() {
+        // 4-bit palette image, one row of three pixels: indices 1, 0, 1.
+        let rows = zlib_compress(&[0, 0x10, 0x10]);
+        let file = png(
+            [0, 0, 0, 3, 0, 0, 0, 1, 4, COLOR_PALETTE, 0, 0, 0],
+            &[(b"PLTE", &[0, 0, 0, 255, 128, 0]), (b"IDAT", &rows)],
+        );
+        let c = Canvas::from_png(&file[..]).unwrap();
+        let orange = Color::new(1.0, 128.0 / 255.0, 0.0);
+        assert_eq!(c.row(0), &[orange, Color::new(0.0, 0.0, 0.0), orange]);
+
+        // 2-bit grayscale, up filter on the second row.
+        let rows = zlib_compress(&[0, 0b00_01_10_11, 2, 0b01_01_00_01]);
+        let file = png(
+            [0, 0, 0, 4, 0, 0, 0, 2, 2, COLOR_GRAY, 0, 0, 0],
+            &[(b"IDAT", &rows)],
+        );
+        let c = Canvas::from_png(&file[..]).unwrap();
+        let gray = |v: f64| Color::new(v, v, v);
+        assert_eq!(
+            c.row(0),
+            &[gray(0.0), gray(1.0 / 3.0), gray(2.0 / 3.0), gray(1.0)]
+        );
+        assert_eq!(
+            c.row(1),
+            &[gray(1.0 / 3.0), gray(2.0 / 3.0), gray(1.0), gray(0.0)]
+        );
+    }
+
+    #[test]
+    fn rejecting_invalid_files() {
+        let message = |file: &[u8]| Canvas::from_png(file).unwrap_err().to_string();
+
+        assert_eq!(message(b"P6 1 1 255"), "not a PNG file");
+
+        let mut out = vec![];
+        Canvas::new(2, 2)
+            .write_png(&mut out, PngFormat::Rgb8)
+            .unwrap();
+        assert_eq!(message(&out[..40]), "PNG file is truncated");
+        out[20] ^= 0xff;
+        assert_eq!(message(&out), "CRC mismatch in IHDR chunk");
+
+        let interlaced = png([0, 0, 0, 1, 0, 0, 0, 1, 8, COLOR_RGB, 0, 0, 1], &[]);
+        assert_eq!(
+            message(&interlaced),
+            "unsupported PNG feature: interlaced images"
+        );
+
+        let bad_depth = png([0, 0, 0, 1, 0, 0, 0, 1, 4, COLOR_RGB, 0, 0, 0], &[]);
+        assert_eq!(
+            message(&bad_depth),
+            "invalid PNG header: bit depth not allowed for color type"
+        );
+    }
+
+    #[test]
+    fn rejecting_hostile_headers() {
+        let message = |file: &[u8]| Canvas::from_png(file).unwrap_err().to_string();
+        let idat = zlib_compress(&[0]);
+
+        let oversized = png(
+            [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 1, 8, COLOR_RGB, 0, 0, 0],
+            &[(b"IDAT", &idat)],
+        );
+        assert_eq!(
+            message(&oversized),
+            "invalid PNG header: dimensions exceed 2^31 - 1"
+        );
+
+        // legal dimensions whose scanlines can't all fit in memory
+        let huge = png(
+            [
+                0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff, 16, COLOR_RGBA, 0, 0, 0,
+            ],
+            &[(b"IDAT", &idat)],
+        );
+        assert_eq!(message(&huge), "invalid PNG header: image is too large");
+
+        // a chunk claiming the largest possible length
+        let mut endless = SIGNATURE.to_vec();
+        endless.extend([0xff, 0xff, 0xff, 0xff]);
+        endless.extend(b"IDAT");
+        assert_eq!(message(&endless), "PNG file is truncated");
+    }
+}
@@ -0,0 +1,597 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::Canvas;
+use crate::color::Color;
+
+/// ASCII P3 lines are wrapped so they never exceed this many characters.
+const MAX_LINE_LENGTH: usize = 70;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PpmFormat {
+    /// `P3`: whitespace separated decimal samples.
+    Ascii,
+    /// `P6`: raw samples, one byte each or two big-endian bytes if `maxval > 255`.
+    Binary,
+}
+
+/// Scales a channel to `0..=maxval`, rounding up like the original `to_ppm`.
+fn quantize(value: f64, maxval: u16) -> u16 {
+    (value * maxval as f64).ceil().clamp(0.0, maxval as f64) as u16
+}
+
+impl Canvas {
+    /// Streams the canvas as a PPM image without building it in memory.
+    ///
+    /// `maxval` is the largest sample value and must be in `1..=65535`;
+    /// anything above 255 selects 16-bit samples.
+    pub fn write_ppm<W: Write>(&self, writer: W, format: PpmFormat, maxval: u16) -> io::Result<()> {
+        if maxval == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ppm maxval must be between 1 and 65535",
+            ));
+        }
+
+        let mut out = BufWriter::new(writer);
+        let magic = match format {
+            PpmFormat::Ascii => "P3",
+            PpmFormat::Binary => "P6",
+        };
+        write!(
+            out,
+            "{}\n{} {}\n{}\n",
+            magic, self.width, self.height, maxval
+        )?;
+
+        match format {
+            PpmFormat::Ascii => self.write_ascii_samples(&mut out, maxval)?,
+            PpmFormat::Binary => self.write_binary_samples(&mut out, maxval)?,
+        }
+
+        out.flush()
+    }
+
+    fn write_ascii_samples<W: Write>(&self, out: &mut W, maxval: u16) -> io::Result<()> {
+        for row in self.rows() {
+            let mut line_length = 0;
+
+            for color in row {
+                for sample in [color.red, color.green, color.blue] {
+                    let sample = quantize(sample, maxval).to_string();
+
+                    if line_length == 0 {
+                        line_length = sample.len();
+                    } else if line_length + 1 + sample.len() > MAX_LINE_LENGTH {
+                        out.write_all(b"\n")?;
+                        line_length = sample.len();
+                    } else {
+                        out.write_all(b" ")?;
+                        line_length += 1 + sample.len();
+                    }
+
+                    out.write_all(sample.as_bytes())?;
+                }
+            }
+
+            if line_length > 0 {
+                out.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_binary_samples<W: Write>(&self, out: &mut W, maxval: u16) -> io::Result<()> {
+        for color in self.pixels() {
+            for sample in [color.red, color.green, color.blue] {
+                let sample = quantize(sample, maxval);
+
+                if maxval > 255 {
+                    out.write_all(&sample.to_be_bytes())?;
+                } else {
+                    out.write_all(&[sample as u8])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Everything that can go wrong while decoding a PNM (`P1`-`P6`) file.
+#[derive(Debug)]
+pub enum PpmError {
+    Io(io::Error),
+    /// The file doesn't start with `P1` through `P6`.
+    UnknownMagic([u8; 2]),
+    /// The file ended while `expected` was still being read.
+    UnexpectedEof {
+        expected: &'static str,
+    },
+    /// A header field or ASCII sample isn't a decimal number.
+    InvalidNumber {
+        field: &'static str,
+        text: String,
+    },
+    InvalidMaxval(u32),
+    SampleOutOfRange {
+        value: u32,
+        maxval: u32,
+    },
+    /// The header field is missing its trailing whitespace.
+    MissingSeparator {
+        after: &'static str,
+    },
+    /// The image has more samples than can be addressed.
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl fmt::Display for PpmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PpmError::Io(err) => write!(f, "failed to read image: {}", err),
+            PpmError::UnknownMagic(magic) => write!(
+                f,
+                "not a PBM/PGM/PPM file (magic number {:?})",
+                String::from_utf8_lossy(magic)
+            ),
+            PpmError::UnexpectedEof { expected } => {
+                write!(f, "file ended while reading {}", expected)
+            }
+            PpmError::InvalidNumber { field, text } => {
+                write!(f, "expected a number for {}, found {:?}", field, text)
+            }
+            PpmError::InvalidMaxval(maxval) => {
+                write!(f, "maxval {} is not between 1 and 65535", maxval)
+            }
+            PpmError::SampleOutOfRange { value, maxval } => {
+                write!(f, "sample {} is larger than maxval {}", value, maxval)
+            }
+            PpmError::MissingSeparator { after } => {
+                write!(f, "expected whitespace after {}", after)
+            }
+            PpmError::TooLarge { width, height } => {
+                write!(f, "image of {}x{} pixels is too large", width, height)
+            }
+        }
+    }
+}
+
+impl Error for PpmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PpmError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PpmError {
+    fn from(err: io::Error) -> Self {
+        PpmError::Io(err)
+    }
+}
+
+/// Walks the bytes of a PNM file, skipping whitespace and `#` comments
+/// between tokens.
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            if byte == b'#' {
+                while self.pos < self.bytes.len() && !matches!(self.bytes[self.pos], b'\n' | b'\r')
+                {
+                    self.pos += 1;
+                }
+            } else if byte.is_ascii_whitespace() {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn number(&mut self, field: &'static str) -> Result<u32, PpmError> {
+        self.skip_whitespace_and_comments();
+
+        let start = self.pos;
+        while self.pos < self.bytes.len()
+            && !self.bytes[self.pos].is_ascii_whitespace()
+            && self.bytes[self.pos] != b'#'
+        {
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return Err(PpmError::UnexpectedEof { expected: field });
+        }
+
+        let text = String::from_utf8_lossy(&self.bytes[start..self.pos]).into_owned();
+        text.parse()
+            .map_err(|_| PpmError::InvalidNumber { field, text })
+    }
+
+    /// Plain PBM bits may be packed together without separators.
+    fn bit(&mut self) -> Result<u32, PpmError> {
+        self.skip_whitespace_and_comments();
+
+        match self.bytes.get(self.pos) {
+            Some(b'0') => {
+                self.pos += 1;
+                Ok(0)
+            }
+            Some(b'1') => {
+                self.pos += 1;
+                Ok(1)
+            }
+            Some(&other) => Err(PpmError::InvalidNumber {
+                field: "bit",
+                text: (other as char).to_string(),
+            }),
+            None => Err(PpmError::UnexpectedEof { expected: "bit" }),
+        }
+    }
+
+    /// Consumes the single whitespace byte that separates the header from
+    /// a binary raster.
+    fn separator(&mut self, after: &'static str) -> Result<(), PpmError> {
+        match self.bytes.get(self.pos) {
+            Some(byte) if byte.is_ascii_whitespace() => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(_) => Err(PpmError::MissingSeparator { after }),
+            None => Err(PpmError::UnexpectedEof { expected: "raster" }),
+        }
+    }
+
+    fn raster(&mut self, len: usize) -> Result<&'a [u8], PpmError> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.pos..end))
+            .ok_or(PpmError::UnexpectedEof { expected: "raster" })?;
+        self.pos += len;
+
+        Ok(bytes)
+    }
+}
+
+fn check_sample(value: u32, maxval: u32) -> Result<f64, PpmError> {
+    if value > maxval {
+        return Err(PpmError::SampleOutOfRange { value, maxval });
+    }
+
+    Ok(value as f64 / maxval as f64)
+}
+
+fn read_maxval(parser: &mut Parser) -> Result<u32, PpmError> {
+    let maxval = parser.number("maxval")?;
+
+    if maxval == 0 || maxval > 65535 {
+        return Err(PpmError::InvalidMaxval(maxval));
+    }
+
+    Ok(maxval)
+}
+
+/// Reads `count` binary samples of one or two big-endian bytes each.
+fn binary_samples(parser: &mut Parser, count: usize, maxval: u32) -> Result<Vec<f64>, PpmError> {
+    let width = if maxval > 255 { 2 } else { 1 };
+    let len = count
+        .checked_mul(width)
+        .ok_or(PpmError::UnexpectedEof { expected: "raster" })?;
+
+    parser
+        .raster(len)?
+        .chunks_exact(width)
+        .map(|bytes| {
+            let value = bytes.iter().fold(0, |acc, &b| acc << 8 | b as u32);
+            check_sample(value, maxval)
+        })
+        .collect()
+}
+
+fn ascii_samples(parser: &mut Parser, count: usize, maxval: u32) -> Result<Vec<f64>, PpmError> {
+    (0..count)
+        .map(|_| check_sample(parser.number("sample")?, maxval))
+        .collect()
+}
+
+impl Canvas {
+    /// Decodes any PNM file: plain (`P1`-`P3`) or raw (`P4`-`P6`) bitmaps,
+    /// graymaps and pixmaps, with comments, any maxval and 16-bit samples.
+    ///
+    /// Samples are scaled by `1 / maxval`; gray values fill all three
+    /// channels, and PBM's `1` (black) becomes 0.0.
+    pub fn from_ppm<R: Read>(mut reader: R) -> Result<Canvas, PpmError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let magic = match bytes.get(..2) {
+            Some(&[b'P', kind @ b'1'..=b'6']) => kind,
+            Some(&[a, b]) => return Err(PpmError::UnknownMagic([a, b])),
+            _ => {
+                return Err(PpmError::UnexpectedEof {
+                    expected: "magic number",
+                })
+            }
+        };
+
+        let mut parser = Parser {
+            bytes: &bytes,
+            pos: 2,
+        };
+        let width = parser.number("width")? as usize;
+        let height = parser.number("height")? as usize;
+        // two bytes for each of three channels is the most any pixel needs,
+        // so past this check the sample and byte counts below can't overflow
+        let pixels = width
+            .checked_mul(height)
+            .filter(|pixels| pixels.checked_mul(6).is_some())
+            .ok_or(PpmError::TooLarge { width, height })?;
+
+        let (channels, samples) = match magic {
+            b'1' => {
+                let bits = (0..pixels)
+                    .map(|_| parser.bit())
+                    .collect::<Result<Vec<_>, _>>()?;
+                (1, bits.into_iter().map(|bit| 1.0 - bit as f64).collect())
+            }
+            b'4' => {
+                parser.separator("height")?;
+                let stride = width.div_ceil(8);
+                let raster = parser.raster(stride * height)?;
+                let samples = (0..pixels)
+                    .map(|i| {
+                        let (x, y) = (i % width, i / width);
+                        let bit = raster[y * stride + x / 8] >> (7 - x % 8) & 1;
+                        1.0 - bit as f64
+                    })
+                    .collect();
+                (1, samples)
+            }
+            b'2' | b'3' => {
+                let channels = if magic == b'2' { 1 } else { 3 };
+                let maxval = read_maxval(&mut parser)?;
+                (
+                    channels,
+                    ascii_samples(&mut parser, pixels * channels, maxval)?,
+                )
+            }
+            _ => {
+                let channels = if magic == b'5' { 1 } else { 3 };
+                let maxval = read_maxval(&mut parser)?;
+                parser.separator("maxval")?;
+                (
+                    channels,
+                    binary_samples(&mut parser, pixels * channels, maxval)?,
+                )
+            }
+        };
+
+        let mut canvas = Canvas::new(width, height);
+        for (color, sample) in canvas.data.iter_mut().zip(samples.chunks_exact(channels)) {
+            *color = match sample {
+                &[gray] => Color::new(gray, gray, gray),
+                _ => Color::new(sample[0], sample[1], sample[2]),
+            };
+        }
+
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_canvas() -> Canvas {
+        let mut c = Canvas::new(5, 3);
+
+        c.write_pixel(0, 0, Color::new(1.5, 0.0, 0.0));
+        c.write_pixel(2, 1, Color::new(0.0, 0.5, 0.0));
+        c.write_pixel(4, 2, Color::new(-0.5, 0.0, 1.0));
+        c
+    }
+
+    #[test]
+    fn streaming_ascii_ppm_matches_to_ppm() {
+        let mut c = Canvas::new(10, 2);
+        c.fill(Color::new(1.0, 0.8, 0.6));
+
+        let mut out = vec![];
+        c.write_ppm(&mut out, PpmFormat::Ascii, 255).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), c.to_ppm());
+    }
+
+    #[test]
+    fn ascii_ppm_pixel_data() {
+        let mut out = vec![];
+        sample_canvas()
+            .write_ppm(&mut out, PpmFormat::Ascii, 255)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "P3
+5 3
+255
+255 0 0 0 0 0 0 0 0 0 0 0 0 0 0
+0 0 0 0 0 0 0 128 0 0 0 0 0 0 0
+0 0 0 0 0 0 0 0 0 0 0 0 0 0 255
+"
+        );
+    }
+
+    #[test]
+    fn binary_ppm_with_eight_bit_samples() {
+        let mut out = vec![];
+        sample_canvas()
+            .write_ppm(&mut out, PpmFormat::Binary, 255)
+            .unwrap();
+
+        let header = b"P6\n5 3\n255\n";
+        assert_eq!(&out[..header.len()], header);
+
+        let samples = &out[header.len()..];
+        assert_eq!(samples.len(), 5 * 3 * 3);
+        assert_eq!(&samples[0..3], &[255, 0, 0]);
+        assert_eq!(&samples[(5 + 2) * 3..(5 + 2) * 3 + 3], &[0, 128, 0]);
+        assert_eq!(&samples[42..45], &[0, 0, 255]);
+    }
+
+    #[test]
+    fn binary_ppm_with_sixteen_bit_samples() {
+        let mut c = Canvas::new(1, 1);
+        c.write_pixel(0, 0, Color::new(1.0, 0.5, 0.0));
+
+        let mut out = vec![];
+        c.write_ppm(&mut out, PpmFormat::Binary, 65535).unwrap();
+
+        let header = b"P6\n1 1\n65535\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], &[0xff, 0xff, 0x80, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn ascii_ppm_with_sixteen_bit_samples() {
+        let mut c = Canvas::new(1, 1);
+        c.write_pixel(0, 0, Color::new(1.0, 0.25, 0.0));
+
+        let mut out = vec![];
+        c.write_ppm(&mut out, PpmFormat::Ascii, 1023).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "P3\n1 1\n1023\n1023 256 0\n"
+        );
+    }
+
+    #[test]
+    fn zero_maxval_is_rejected() {
+        let err = Canvas::new(1, 1)
+            .write_ppm(vec![], PpmFormat::Binary, 0)
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reading_plain_ppm_with_comments() {
+        let file = b"P3\n# made by hand\n2 1 # width height\n15\n15 0 0  0 15 # trailing\n 5\n";
+        let c = Canvas::from_ppm(&file[..]).unwrap();
+
+        assert_eq!((c.width, c.height), (2, 1));
+        assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(c.pixel_at(1, 0), Color::new(0.0, 1.0, 1.0 / 3.0));
+    }
+
+    #[test]
+    fn round_tripping_through_ascii_and_binary_ppm() {
+        for (format, maxval) in [
+            (PpmFormat::Ascii, 255),
+            (PpmFormat::Binary, 255),
+            (PpmFormat::Binary, 65535),
+        ] {
+            let original = sample_canvas();
+            let mut out = vec![];
+            original.write_ppm(&mut out, format, maxval).unwrap();
+
+            let c = Canvas::from_ppm(&out[..]).unwrap();
+            assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 0.0, 0.0));
+            assert_eq!(c.pixel_at(4, 2), Color::new(0.0, 0.0, 1.0));
+            assert!((c.pixel_at(2, 1).green - 0.5).abs() <= 1.0 / maxval as f64);
+        }
+    }
+
+    #[test]
+    fn reading_graymaps() {
+        let plain = Canvas::from_ppm(&b"P2 2 1 4 0 1"[..]).unwrap();
+        assert_eq!(plain.pixel_at(1, 0), Color::new(0.25, 0.25, 0.25));
+
+        let raw = Canvas::from_ppm(&b"P5 2 1 1000\n\x03\xe8\x01\xf4"[..]).unwrap();
+        assert_eq!(raw.pixel_at(0, 0), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(raw.pixel_at(1, 0), Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn reading_bitmaps() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let black = Color::new(0.0, 0.0, 0.0);
+
+        let plain = Canvas::from_ppm(&b"P1\n3 2\n010\n1 0 0"[..]).unwrap();
+        assert_eq!(plain.row(0), &[white, black, white]);
+        assert_eq!(plain.row(1), &[black, white, white]);
+
+        // rows are padded to a whole byte
+        let raw = Canvas::from_ppm(&b"P4\n10 2\n\x80\x40\x01\x80"[..]).unwrap();
+        assert_eq!(raw.pixel_at(0, 0), black);
+        assert_eq!(raw.pixel_at(1, 0), white);
+        assert_eq!(raw.pixel_at(9, 0), black);
+        assert_eq!(raw.pixel_at(7, 1), black);
+        assert_eq!(raw.pixel_at(8, 1), black);
+        assert_eq!(raw.pixel_at(9, 1), white);
+    }
+
+    #[test]
+    fn descriptive_parse_errors() {
+        let message = |file: &[u8]| Canvas::from_ppm(file).unwrap_err().to_string();
+
+        assert_eq!(
+            message(b"GIF89a"),
+            "not a PBM/PGM/PPM file (magic number \"GI\")"
+        );
+        assert_eq!(
+            message(b"P3 2 x 255"),
+            "expected a number for height, found \"x\""
+        );
+        assert_eq!(
+            message(b"P3 1 1 0 0 0 0"),
+            "maxval 0 is not between 1 and 65535"
+        );
+        assert_eq!(
+            message(b"P3 1 1 9 0 10 0"),
+            "sample 10 is larger than maxval 9"
+        );
+        assert_eq!(
+            message(b"P3 2 1 255 1 2 3"),
+            "file ended while reading sample"
+        );
+        assert_eq!(
+            message(b"P6 2 1 255\n\x00\x00"),
+            "file ended while reading raster"
+        );
+        assert_eq!(
+            message(b"P6 1 1 255#\n\x00\x00\x00"),
+            "expected whitespace after maxval"
+        );
+    }
+
+    #[test]
+    fn rejecting_oversized_headers() {
+        let message = |file: &[u8]| Canvas::from_ppm(file).unwrap_err().to_string();
+        let huge = format!("{} {}", u32::MAX, u32::MAX);
+
+        for magic in ["P1", "P3", "P5", "P6"] {
+            assert_eq!(
+                message(format!("{} {} 255\n", magic, huge).as_bytes()),
+                format!("image of {}x{} pixels is too large", u32::MAX, u32::MAX)
+            );
+        }
+        assert_eq!(
+            message(b"P6 18446744073709551615 2 255\n"),
+            "expected a number for width, found \"18446744073709551615\""
+        );
+    }
+}
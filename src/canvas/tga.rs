@@ -0,0 +1,504 @@
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
+
+use super::{alpha_plane, quantize, Canvas};
+use crate::color::Color;
+
+const HEADER_SIZE: usize = 18;
+pub(super) const FOOTER: &[u8; 26] = b"\0\0\0\0\0\0\0\0TRUEVISION-XFILE.\0";
+
+const TYPE_COLOR_MAPPED: u8 = 1;
+const TYPE_TRUE_COLOR: u8 = 2;
+const TYPE_GRAY: u8 = 3;
+/// Run-length encoded variants are the plain types plus this.
+const TYPE_RLE: u8 = 8;
+
+const DESCRIPTOR_RIGHT_TO_LEFT: u8 = 0x10;
+const DESCRIPTOR_TOP_DOWN: u8 = 0x20;
+const DESCRIPTOR_ALPHA_BITS: u8 = 0x0f;
+
+const MAX_PACKET: usize = 128;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TgaFormat {
+    Rgb24,
+    /// 32-bit BGRA, with alpha taken from `Canvas::alpha`; fully opaque
+    /// when the canvas has no alpha plane.
+    Rgba32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TgaCompression {
+    None,
+    Rle,
+}
+
+#[derive(Debug)]
+pub enum TgaError {
+    Io(io::Error),
+    Truncated,
+    Unsupported(&'static str),
+    Invalid(&'static str),
+}
+
+impl fmt::Display for TgaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TgaError::Io(err) => write!(f, "failed to read image: {}", err),
+            TgaError::Truncated => write!(f, "TGA file is truncated"),
+            TgaError::Unsupported(what) => write!(f, "unsupported TGA feature: {}", what),
+            TgaError::Invalid(reason) => write!(f, "invalid TGA file: {}", reason),
+        }
+    }
+}
+
+impl Error for TgaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TgaError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TgaError {
+    fn from(err: io::Error) -> Self {
+        TgaError::Io(err)
+    }
+}
+
+/// Packs a row of pixels into RLE packets. A header byte with the top bit
+/// set repeats the following pixel `(header & 0x7f) + 1` times; otherwise
+/// `header + 1` raw pixels follow.
+fn encode_rle_row(pixels: &[&[u8]], out: &mut Vec<u8>) {
+    let mut i = 0;
+
+    while i < pixels.len() {
+        let run = pixels[i..]
+            .iter()
+            .take(MAX_PACKET)
+            .take_while(|&&p| p == pixels[i])
+            .count();
+
+        if run >= 2 {
+            out.push(0x80 | (run - 1) as u8);
+            out.extend_from_slice(pixels[i]);
+            i += run;
+            continue;
+        }
+
+        let start = i;
+        while i < pixels.len() && i - start < MAX_PACKET {
+            if i + 1 < pixels.len() && pixels[i] == pixels[i + 1] {
+                break;
+            }
+            i += 1;
+        }
+
+        out.push((i - start - 1) as u8);
+        for pixel in &pixels[start..i] {
+            out.extend_from_slice(pixel);
+        }
+    }
+}
+
+/// Decodes one stored pixel of `depth` bits into a color and the alpha it
+/// would have if the image declares alpha bits.
+fn decode_pixel(bytes: &[u8], depth: u8, gray: bool) -> (Color, f64) {
+    match (depth, gray) {
+        (_, true) => {
+            let v = bytes[0] as f64 / 255.0;
+            let alpha = if depth == 16 {
+                bytes[1] as f64 / 255.0
+            } else {
+                1.0
+            };
+            (Color::new(v, v, v), alpha)
+        }
+        (15 | 16, _) => {
+            let v = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let color = Color::new(
+                (v >> 10 & 0x1f) as f64 / 31.0,
+                (v >> 5 & 0x1f) as f64 / 31.0,
+                (v & 0x1f) as f64 / 31.0,
+            );
+            let alpha = if depth == 16 { (v >> 15) as f64 } else { 1.0 };
+            (color, alpha)
+        }
+        _ => {
+            let color = Color::new(
+                bytes[2] as f64 / 255.0,
+                bytes[1] as f64 / 255.0,
+                bytes[0] as f64 / 255.0,
+            );
+            let alpha = if depth == 32 {
+                bytes[3] as f64 / 255.0
+            } else {
+                1.0
+            };
+            (color, alpha)
+        }
+    }
+}
+
+impl Canvas {
+    /// Writes a Truevision TGA file with a bottom-left origin and the
+    /// TGA 2.0 footer. `Rgba32` output takes the canvas's alpha plane, or
+    /// is fully opaque when it has none.
+    pub fn write_tga<W: Write>(
+        &self,
+        writer: W,
+        format: TgaFormat,
+        compression: TgaCompression,
+    ) -> io::Result<()> {
+        if self.width > u16::MAX as usize || self.height > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "TGA images are at most 65535 pixels on a side",
+            ));
+        }
+
+        let (depth, alpha_bits) = match format {
+            TgaFormat::Rgb24 => (24, 0),
+            TgaFormat::Rgba32 => (32, 8),
+        };
+        let image_type = match compression {
+            TgaCompression::None => TYPE_TRUE_COLOR,
+            TgaCompression::Rle => TYPE_TRUE_COLOR + TYPE_RLE,
+        };
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&(self.width as u16).to_le_bytes());
+        header[14..16].copy_from_slice(&(self.height as u16).to_le_bytes());
+        header[16] = depth;
+        header[17] = alpha_bits;
+
+        let mut out = BufWriter::new(writer);
+        out.write_all(&header)?;
+
+        let mut encoded = vec![];
+        for y in (0..self.height).rev() {
+            let pixels: Vec<[u8; 4]> = self
+                .row(y)
+                .iter()
+                .enumerate()
+                .map(|(x, color)| {
+                    [
+                        quantize(color.blue, 255.0) as u8,
+                        quantize(color.green, 255.0) as u8,
+                        quantize(color.red, 255.0) as u8,
+                        quantize(self.opacity(y * self.width + x), 255.0) as u8,
+                    ]
+                })
+                .collect();
+            let pixels: Vec<&[u8]> = pixels.iter().map(|p| &p[..depth as usize / 8]).collect();
+
+            match compression {
+                TgaCompression::None => pixels.iter().for_each(|p| encoded.extend_from_slice(p)),
+                TgaCompression::Rle => encode_rle_row(&pixels, &mut encoded),
+            }
+            out.write_all(&encoded)?;
+            encoded.clear();
+        }
+
+        out.write_all(FOOTER)?;
+        out.flush()
+    }
+
+    /// Reads color-mapped, true-color and grayscale TGA files, raw or run
+    /// length encoded, from any of the four origins. When the descriptor
+    /// declares alpha bits they fill the canvas's alpha plane, unless every
+    /// pixel is opaque.
+    pub fn from_tga<R: Read>(mut reader: R) -> Result<Canvas, TgaError> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        let header = bytes.get(..HEADER_SIZE).ok_or(TgaError::Truncated)?;
+        let id_length = header[0] as usize;
+        let has_color_map = header[1] == 1;
+        let image_type = header[2];
+        let map_first = u16::from_le_bytes([header[3], header[4]]) as usize;
+        let map_length = u16::from_le_bytes([header[5], header[6]]) as usize;
+        let map_depth = header[7];
+        let width = u16::from_le_bytes([header[12], header[13]]) as usize;
+        let height = u16::from_le_bytes([header[14], header[15]]) as usize;
+        let depth = header[16];
+        let descriptor = header[17];
+
+        let rle = image_type & TYPE_RLE != 0;
+        let kind = image_type & !TYPE_RLE;
+        match (kind, depth) {
+            (TYPE_COLOR_MAPPED, 8 | 16) if has_color_map => {}
+            (TYPE_TRUE_COLOR, 15 | 16 | 24 | 32) => {}
+            (TYPE_GRAY, 8 | 16) => {}
+            (TYPE_COLOR_MAPPED | TYPE_TRUE_COLOR | TYPE_GRAY, _) => {
+                return Err(TgaError::Unsupported("pixel depth for image type"))
+            }
+            _ => return Err(TgaError::Unsupported("image type")),
+        }
+        if kind == TYPE_COLOR_MAPPED && !matches!(map_depth, 15 | 16 | 24 | 32) {
+            return Err(TgaError::Unsupported("color map entry size"));
+        }
+
+        let mut pos = HEADER_SIZE + id_length;
+        let mut color_map = vec![];
+        if has_color_map {
+            let entry_size = (map_depth as usize).div_ceil(8);
+            let entries = bytes
+                .get(pos..pos + map_length * entry_size)
+                .ok_or(TgaError::Truncated)?;
+            // true-color and grayscale images may carry a map they never
+            // index, in any entry size, so only mapped images decode it
+            if kind == TYPE_COLOR_MAPPED {
+                color_map = entries
+                    .chunks_exact(entry_size)
+                    .map(|entry| decode_pixel(entry, map_depth, false))
+                    .collect();
+            }
+            pos += map_length * entry_size;
+        }
+
+        let pixel_size = (depth as usize).div_ceil(8);
+        let to_color = |pixel: &[u8]| -> Result<(Color, f64), TgaError> {
+            if kind != TYPE_COLOR_MAPPED {
+                return Ok(decode_pixel(pixel, depth, kind == TYPE_GRAY));
+            }
+
+            let index = pixel.iter().rev().fold(0, |acc, &b| acc << 8 | b as usize);
+            index
+                .checked_sub(map_first)
+                .and_then(|i| color_map.get(i).copied())
+                .ok_or(TgaError::Invalid("color map index out of range"))
+        };
+
+        // nothing is sized from the header alone: runs grow the pixels as
+        // they decode and raw data is checked to be present first, so the
+        // canvas below is only allocated once the file has covered it
+        let count = width * height;
+        let mut stored = Vec::new();
+        if rle {
+            while stored.len() < count {
+                let packet = *bytes.get(pos).ok_or(TgaError::Truncated)?;
+                let repeat = (packet & 0x7f) as usize + 1;
+                pos += 1;
+
+                if packet & 0x80 != 0 {
+                    let pixel = bytes
+                        .get(pos..pos + pixel_size)
+                        .ok_or(TgaError::Truncated)?;
+                    let color = to_color(pixel)?;
+                    stored.extend(std::iter::repeat_n(color, repeat));
+                    pos += pixel_size;
+                } else {
+                    let raw = bytes
+                        .get(pos..pos + repeat * pixel_size)
+                        .ok_or(TgaError::Truncated)?;
+                    for pixel in raw.chunks_exact(pixel_size) {
+                        stored.push(to_color(pixel)?);
+                    }
+                    pos += repeat * pixel_size;
+                }
+            }
+
+            if stored.len() > count {
+                return Err(TgaError::Invalid("run-length packet overruns the image"));
+            }
+        } else {
+            let raw = count
+                .checked_mul(pixel_size)
+                .and_then(|size| bytes.get(pos..pos.checked_add(size)?))
+                .ok_or(TgaError::Truncated)?;
+            stored.reserve_exact(count);
+            for pixel in raw.chunks_exact(pixel_size) {
+                stored.push(to_color(pixel)?);
+            }
+        }
+
+        let mut canvas = Canvas::new(width, height);
+        let mut alpha = vec![1.0; count];
+        for (i, (color, a)) in stored.into_iter().enumerate() {
+            let (mut x, mut y) = (i % width, i / width);
+            if descriptor & DESCRIPTOR_RIGHT_TO_LEFT != 0 {
+                x = width - 1 - x;
+            }
+            if descriptor & DESCRIPTOR_TOP_DOWN == 0 {
+                y = height - 1 - y;
+            }
+            canvas.write_pixel(x, y, color);
+            alpha[y * width + x] = a;
+        }
+
+        if descriptor & DESCRIPTOR_ALPHA_BITS != 0 {
+            canvas.alpha = alpha_plane(alpha);
+        }
+        Ok(canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(width: usize, height: usize) -> Canvas {
+        let mut c = Canvas::new(width, height);
+
+        for (x, y, color) in c.enumerate_pixels_mut() {
+            if x < width / 2 {
+                *color = Color::new(0.0, 0.0, 1.0);
+            } else if (x + y) % 2 == 0 {
+                *color = Color::new(1.0, 1.0, 0.0);
+            }
+        }
+        c
+    }
+
+    fn header(image_type: u8, width: u16, height: u16, depth: u8, descriptor: u8) -> Vec<u8> {
+        let mut header = vec![0; HEADER_SIZE];
+        header[2] = image_type;
+        header[12..14].copy_from_slice(&width.to_le_bytes());
+        header[14..16].copy_from_slice(&height.to_le_bytes());
+        header[16] = depth;
+        header[17] = descriptor;
+        header
+    }
+
+    #[test]
+    fn writing_uncompressed_tga_bottom_up() {
+        let mut c = Canvas::new(2, 2);
+        c.write_pixel(0, 0, Color::new(1.0, 0.0, 0.0));
+
+        let mut out = vec![];
+        c.write_tga(&mut out, TgaFormat::Rgba32, TgaCompression::None)
+            .unwrap();
+
+        assert_eq!(&out[..HEADER_SIZE], &header(2, 2, 2, 32, 8)[..]);
+        // the bottom row is stored first
+        assert_eq!(&out[HEADER_SIZE..HEADER_SIZE + 4], &[0, 0, 0, 255]);
+        assert_eq!(&out[HEADER_SIZE + 8..HEADER_SIZE + 12], &[0, 0, 255, 255]);
+        assert_eq!(&out[out.len() - FOOTER.len()..], FOOTER);
+    }
+
+    #[test]
+    fn round_tripping_every_format() {
+        let c = checker(9, 4);
+
+        for format in [TgaFormat::Rgb24, TgaFormat::Rgba32] {
+            for compression in [TgaCompression::None, TgaCompression::Rle] {
+                let mut out = vec![];
+                c.write_tga(&mut out, format, compression).unwrap();
+
+                assert_eq!(Canvas::from_tga(&out[..]).unwrap(), c);
+            }
+        }
+    }
+
+    #[test]
+    fn run_length_encoding_shrinks_flat_images() {
+        let mut c = Canvas::new(300, 20);
+        c.fill(Color::new(0.5, 0.5, 0.5));
+
+        let mut out = vec![];
+        c.write_tga(&mut out, TgaFormat::Rgb24, TgaCompression::Rle)
+            .unwrap();
+
+        // three packets per row: 128 + 128 + 44
+        assert_eq!(out.len(), HEADER_SIZE + 20 * 3 * 4 + FOOTER.len());
+    }
+
+    #[test]
+    fn reading_top_down_right_to_left_grayscale() {
+        let mut file = header(3, 2, 2, 8, DESCRIPTOR_TOP_DOWN | DESCRIPTOR_RIGHT_TO_LEFT);
+        file.extend([0, 255, 51, 102]);
+
+        let c = Canvas::from_tga(&file[..]).unwrap();
+        let gray = |v: f64| Color::new(v, v, v);
+
+        assert_eq!(c.row(0), &[gray(1.0), gray(0.0)]);
+        assert_eq!(c.row(1), &[gray(0.4), gray(0.2)]);
+    }
+
+    #[test]
+    fn reading_rle_color_mapped_images() {
+        let mut file = header(9, 4, 1, 8, DESCRIPTOR_TOP_DOWN);
+        file[1] = 1;
+        file[3..5].copy_from_slice(&10u16.to_le_bytes());
+        file[5..7].copy_from_slice(&2u16.to_le_bytes());
+        file[7] = 16;
+        // 16-bit entries: pure red then pure blue
+        file.extend([0x00, 0x7c, 0x1f, 0x00]);
+        // a run of three index 10, then one raw index 11
+        file.extend([0x82, 10, 0x00, 11]);
+
+        let c = Canvas::from_tga(&file[..]).unwrap();
+        let (red, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+
+        assert_eq!(c.row(0), &[red, red, red, blue]);
+    }
+
+    /// A header declaring an unused color map of two entries, followed by
+    /// the map's bytes.
+    fn with_color_map(mut file: Vec<u8>, map_depth: u8) -> Vec<u8> {
+        file[1] = 1;
+        file[5..7].copy_from_slice(&2u16.to_le_bytes());
+        file[7] = map_depth;
+        file.extend(vec![7; 2 * (map_depth as usize).div_ceil(8)]);
+        file
+    }
+
+    #[test]
+    fn skipping_empty_color_maps_in_true_color_images() {
+        let mut file = with_color_map(header(2, 1, 1, 24, 0), 0);
+        file.extend([0, 0, 255]);
+
+        let c = Canvas::from_tga(&file[..]).unwrap();
+        assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn skipping_narrow_color_maps_in_grayscale_images() {
+        let mut file = with_color_map(header(3, 1, 1, 8, 0), 8);
+        file.push(255);
+
+        let c = Canvas::from_tga(&file[..]).unwrap();
+        assert_eq!(c.pixel_at(0, 0), Color::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn rejecting_invalid_files() {
+        let message = |file: &[u8]| Canvas::from_tga(file).unwrap_err().to_string();
+
+        assert_eq!(message(&[0; 10]), "TGA file is truncated");
+        assert_eq!(
+            message(&header(32, 1, 1, 24, 0)),
+            "unsupported TGA feature: image type"
+        );
+        assert_eq!(
+            message(&header(2, 1, 1, 8, 0)),
+            "unsupported TGA feature: pixel depth for image type"
+        );
+        assert_eq!(message(&header(10, 1, 1, 24, 0)), "TGA file is truncated");
+
+        let mut overrun = header(10, 2, 1, 24, 0);
+        overrun.extend([0x82, 0, 0, 0]);
+        assert_eq!(
+            message(&overrun),
+            "invalid TGA file: run-length packet overruns the image"
+        );
+    }
+
+    #[test]
+    fn rejecting_huge_headers_without_the_data() {
+        let message = |file: &[u8]| Canvas::from_tga(file).unwrap_err().to_string();
+
+        let mut raw = header(2, 65535, 65535, 24, 0);
+        raw.extend([0; 3]);
+        assert_eq!(message(&raw), "TGA file is truncated");
+
+        // a single run cannot stand in for the whole image
+        let mut rle = header(10, 65535, 65535, 24, 0);
+        rle.extend([0xff, 1, 2, 3]);
+        assert_eq!(message(&rle), "TGA file is truncated");
+    }
+}
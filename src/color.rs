@@ -1,8 +1,17 @@
+#![allow(dead_code)]
 use std::ops::{Add, Mul, Sub};
 
+pub mod alpha;
+pub mod kelvin;
+pub mod named;
+pub mod space;
+pub mod tonemap;
+pub mod transfer;
+
+#[cfg(test)]
 use crate::utils::equal;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Color {
     pub red: f64,
     pub green: f64,
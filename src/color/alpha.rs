@@ -0,0 +1,39 @@
+use super::Color;
+
+fn scale(color: Color, factor: f64) -> Color {
+    Color::new(
+        color.red * factor,
+        color.green * factor,
+        color.blue * factor,
+    )
+}
+
+impl Color {
+    /// Scales the color by its coverage, the form compositing math wants.
+    pub fn premultiply(self, alpha: f64) -> Color {
+        scale(self, alpha)
+    }
+
+    /// Undoes `premultiply`. Fully transparent pixels become black.
+    pub fn unpremultiply(self, alpha: f64) -> Color {
+        if alpha == 0.0 {
+            Color::new(0.0, 0.0, 0.0)
+        } else {
+            scale(self, 1.0 / alpha)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiplying_colors() {
+        let color = Color::new(0.5, 1.0, 0.2);
+
+        assert_eq!(color.premultiply(0.5), Color::new(0.25, 0.5, 0.1));
+        assert_eq!(color.premultiply(0.5).unpremultiply(0.5), color);
+        assert_eq!(color.unpremultiply(0.0), Color::new(0.0, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,90 @@
+use super::space::Xyz;
+use super::Color;
+
+/// The range covered by Kim et al.'s fit of the Planckian locus.
+pub const MIN_KELVIN: f64 = 1667.0;
+pub const MAX_KELVIN: f64 = 25000.0;
+
+/// CIE 1931 chromaticity of a blackbody at `kelvin`, using the cubic spline
+/// approximation of the Planckian locus from Kim et al. (2002).
+fn planckian_locus(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(MIN_KELVIN, MAX_KELVIN);
+    let (t2, t3) = (t * t, t * t * t);
+
+    let x = if t <= 4000.0 {
+        -0.266_123_9e9 / t3 - 0.234_358_9e6 / t2 + 0.877_695_6e3 / t + 0.179_910
+    } else {
+        -3.025_846_9e9 / t3 + 2.107_037_9e6 / t2 + 0.222_634_7e3 / t + 0.240_390
+    };
+
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.106_381_4 * x3 - 1.348_110_20 * x2 + 2.185_558_32 * x - 0.202_196_83
+    } else if t <= 4000.0 {
+        -0.954_947_6 * x3 - 1.374_185_93 * x2 + 2.091_370_15 * x - 0.167_488_67
+    } else {
+        3.081_758_0 * x3 - 5.873_386_70 * x2 + 3.751_129_97 * x - 0.370_014_83
+    };
+
+    (x, y)
+}
+
+impl Color {
+    /// The linear RGB tint of a blackbody radiator at `kelvin`, scaled so
+    /// the brightest channel is 1.0. Multiply by an intensity to get a
+    /// light color. Temperatures outside 1667–25000 K are clamped.
+    ///
+    /// Candles are around 1900 K, tungsten bulbs 2700 K and overcast
+    /// daylight 6500 K, which comes out close to white.
+    pub fn from_kelvin(kelvin: f64) -> Color {
+        let (x, y) = planckian_locus(kelvin);
+        let color = Color::from_xyz(Xyz {
+            x: x / y,
+            y: 1.0,
+            z: (1.0 - x - y) / y,
+        });
+
+        // Very warm or cool temperatures fall slightly outside sRGB.
+        let color = Color::new(
+            color.red.max(0.0),
+            color.green.max(0.0),
+            color.blue.max(0.0),
+        );
+        let max = color.red.max(color.green).max(color.blue);
+        Color::new(color.red / max, color.green / max, color.blue / max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn following_the_planckian_locus() {
+        let (x, y) = planckian_locus(6504.0);
+        assert!((x - 0.3135).abs() < 0.001 && (y - 0.3237).abs() < 0.001);
+
+        let (x, y) = planckian_locus(2856.0);
+        assert!((x - 0.4476).abs() < 0.001 && (y - 0.4074).abs() < 0.001);
+    }
+
+    #[test]
+    fn converting_temperatures_to_colors() {
+        let daylight = Color::from_kelvin(6500.0);
+        assert!(daylight.red.min(daylight.green).min(daylight.blue) > 0.9);
+
+        let tungsten = Color::from_kelvin(2700.0);
+        assert_eq!(tungsten.red, 1.0);
+        assert!(tungsten.red > tungsten.green && tungsten.green > tungsten.blue);
+
+        let sky = Color::from_kelvin(12000.0);
+        assert_eq!(sky.blue, 1.0);
+        assert!(sky.red < sky.green);
+    }
+
+    #[test]
+    fn clamping_out_of_range_temperatures() {
+        assert_eq!(Color::from_kelvin(500.0), Color::from_kelvin(MIN_KELVIN));
+        assert_eq!(Color::from_kelvin(1e6), Color::from_kelvin(MAX_KELVIN));
+    }
+}
@@ -0,0 +1,282 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use super::Color;
+
+/// The CSS Color Module Level 4 named colors, sorted by name. Where CSS and
+/// X11 disagree (gray, green, maroon, purple) the CSS value wins.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ParseColorError {
+    /// A `#` color that isn't 3 or 6 hex digits.
+    InvalidHex(String),
+    UnknownName(String),
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseColorError::InvalidHex(text) => write!(f, "invalid hex color {:?}", text),
+            ParseColorError::UnknownName(name) => write!(f, "unknown color name {:?}", name),
+        }
+    }
+}
+
+impl Error for ParseColorError {}
+
+/// Converts a packed `0xrrggbb` sRGB value to a linear color.
+fn from_packed(rgb: u32) -> Color {
+    let channel = |shift: u32| (rgb >> shift & 0xff) as f64 / 255.0;
+    Color::from_srgb(Color::new(channel(16), channel(8), channel(0)))
+}
+
+impl Color {
+    /// Looks up a CSS color name, ignoring case. Like hex colors, named
+    /// colors are sRGB values and come back linearized.
+    pub fn named(name: &str) -> Option<Color> {
+        let name = name.to_ascii_lowercase();
+        NAMED_COLORS
+            .binary_search_by(|&(entry, _)| entry.cmp(name.as_str()))
+            .ok()
+            .map(|i| from_packed(NAMED_COLORS[i].1))
+    }
+
+    /// Parses `#rgb` or `#rrggbb` as an sRGB color and linearizes it.
+    pub fn from_hex(text: &str) -> Result<Color, ParseColorError> {
+        let invalid = || ParseColorError::InvalidHex(text.to_string());
+        let digits = text.strip_prefix('#').ok_or_else(invalid)?;
+
+        if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let value = match digits.len() {
+            // each digit is doubled: #f80 is #ff8800
+            3 => u32::from_str_radix(digits, 16)
+                .map(|v| (v >> 8 & 0xf) * 0x110000 + (v >> 4 & 0xf) * 0x1100 + (v & 0xf) * 0x11),
+            6 => u32::from_str_radix(digits, 16),
+            _ => return Err(invalid()),
+        };
+
+        value.map(from_packed).map_err(|_| invalid())
+    }
+}
+
+impl FromStr for Color {
+    type Err = ParseColorError;
+
+    /// Accepts hex colors and CSS color names, e.g. `"#ff8800"` or
+    /// `"CornflowerBlue"`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+
+        if text.starts_with('#') {
+            Color::from_hex(text)
+        } else {
+            Color::named(text).ok_or_else(|| ParseColorError::UnknownName(text.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    fn srgb(color: Color) -> [u8; 3] {
+        let encoded = color.to_srgb();
+        [encoded.red, encoded.green, encoded.blue].map(|v| (v * 255.0).round() as u8)
+    }
+
+    #[test]
+    fn the_table_is_sorted() {
+        assert!(NAMED_COLORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn parsing_hex_colors() {
+        assert_eq!(srgb(Color::from_hex("#ff8800").unwrap()), [255, 136, 0]);
+        assert_eq!(srgb(Color::from_hex("#F80").unwrap()), [255, 136, 0]);
+        assert_eq!(Color::from_hex("#000").unwrap(), Color::new(0.0, 0.0, 0.0));
+
+        // hex values are sRGB, so mid gray is much darker in linear light
+        assert!(equal(Color::from_hex("#808080").unwrap().red, 0.21586));
+
+        for bad in ["ff8800", "#ff880", "#ggg", "#+12", "#ff88001"] {
+            assert_eq!(
+                Color::from_hex(bad),
+                Err(ParseColorError::InvalidHex(bad.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn looking_up_names() {
+        assert_eq!(
+            srgb(Color::named("cornflowerblue").unwrap()),
+            [100, 149, 237]
+        );
+        assert_eq!(srgb(Color::named("RebeccaPurple").unwrap()), [102, 51, 153]);
+        assert_eq!(Color::named("grey"), Color::named("gray"));
+        assert_eq!(Color::named("blurple"), None);
+    }
+
+    #[test]
+    fn parsing_strings() {
+        assert_eq!(" white ".parse::<Color>(), Ok(Color::new(1.0, 1.0, 1.0)));
+        assert_eq!("#00ff00".parse::<Color>(), "lime".parse::<Color>());
+
+        let err = "blurple".parse::<Color>().unwrap_err();
+        assert_eq!(err.to_string(), "unknown color name \"blurple\"");
+    }
+}
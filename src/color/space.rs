@@ -0,0 +1,455 @@
+use super::Color;
+use crate::canvas::Canvas;
+
+/// Linear sRGB (Rec.709 primaries, D65 white) to CIE XYZ.
+const RGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.412_456_4, 0.357_576_1, 0.180_437_5],
+    [0.212_672_9, 0.715_152_2, 0.072_175_0],
+    [0.019_333_9, 0.119_192_0, 0.950_304_1],
+];
+
+const XYZ_TO_RGB: [[f64; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266_0, 1.876_010_8, 0.041_556_0],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+/// The D65 reference white Lab is measured against.
+const WHITE: Xyz = Xyz {
+    x: 0.950_47,
+    y: 1.0,
+    z: 1.088_83,
+};
+
+/// Hue in degrees (`0.0..360.0`), saturation and value in `0.0..=1.0`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hsv {
+    pub hue: f64,
+    pub saturation: f64,
+    pub value: f64,
+}
+
+/// Hue in degrees (`0.0..360.0`), saturation and lightness in `0.0..=1.0`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hsl {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64,
+}
+
+/// CIE 1931 XYZ, with `y` being relative luminance.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Xyz {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+/// CIELAB relative to D65, with `l` in `0.0..=100.0` for colors no brighter
+/// than white.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+fn transform(matrix: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    matrix.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Hue in degrees and the max and min channels, shared by HSV and HSL.
+fn hue_and_range(color: Color) -> (f64, f64, f64) {
+    let Color { red, green, blue } = color;
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == red {
+        60.0 * ((green - blue) / delta).rem_euclid(6.0)
+    } else if max == green {
+        60.0 * ((blue - red) / delta + 2.0)
+    } else {
+        60.0 * ((red - green) / delta + 4.0)
+    };
+
+    (hue, max, min)
+}
+
+/// Builds a color from a hue, its chroma and the amount added to every
+/// channel.
+fn from_hue(hue: f64, chroma: f64, offset: f64) -> Color {
+    let h = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+
+    let (red, green, blue) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    Color::new(red + offset, green + offset, blue + offset)
+}
+
+fn lab_f(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+
+    if t > DELTA.powi(3) {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inverse(t: f64) -> f64 {
+    const DELTA: f64 = 6.0 / 29.0;
+
+    if t > DELTA {
+        t.powi(3)
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+impl Color {
+    /// Relative luminance of a linear color, the `Y` of `to_xyz`.
+    pub fn luminance(self) -> f64 {
+        let [_, y, _] = RGB_TO_XYZ;
+        y[0] * self.red + y[1] * self.green + y[2] * self.blue
+    }
+
+    pub fn to_hsv(self) -> Hsv {
+        let (hue, max, min) = hue_and_range(self);
+        let saturation = if max == 0.0 { 0.0 } else { (max - min) / max };
+
+        Hsv {
+            hue,
+            saturation,
+            value: max,
+        }
+    }
+
+    pub fn from_hsv(hsv: Hsv) -> Color {
+        let chroma = hsv.value * hsv.saturation;
+        from_hue(hsv.hue, chroma, hsv.value - chroma)
+    }
+
+    pub fn to_hsl(self) -> Hsl {
+        let (hue, max, min) = hue_and_range(self);
+        let lightness = (max + min) / 2.0;
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+
+        Hsl {
+            hue,
+            saturation,
+            lightness,
+        }
+    }
+
+    pub fn from_hsl(hsl: Hsl) -> Color {
+        let chroma = (1.0 - (2.0 * hsl.lightness - 1.0).abs()) * hsl.saturation;
+        from_hue(hsl.hue, chroma, hsl.lightness - chroma / 2.0)
+    }
+
+    /// Converts a linear sRGB color to XYZ.
+    pub fn to_xyz(self) -> Xyz {
+        let [x, y, z] = transform(&RGB_TO_XYZ, [self.red, self.green, self.blue]);
+        Xyz { x, y, z }
+    }
+
+    /// Converts XYZ to linear sRGB. Colors outside the sRGB gamut come back
+    /// with negative channels.
+    pub fn from_xyz(xyz: Xyz) -> Color {
+        let [red, green, blue] = transform(&XYZ_TO_RGB, [xyz.x, xyz.y, xyz.z]);
+        Color::new(red, green, blue)
+    }
+
+    pub fn to_lab(self) -> Lab {
+        let xyz = self.to_xyz();
+        let fx = lab_f(xyz.x / WHITE.x);
+        let fy = lab_f(xyz.y / WHITE.y);
+        let fz = lab_f(xyz.z / WHITE.z);
+
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    pub fn from_lab(lab: Lab) -> Color {
+        let fy = (lab.l + 16.0) / 116.0;
+        let fx = fy + lab.a / 500.0;
+        let fz = fy - lab.b / 200.0;
+
+        Color::from_xyz(Xyz {
+            x: WHITE.x * lab_f_inverse(fx),
+            y: WHITE.y * lab_f_inverse(fy),
+            z: WHITE.z * lab_f_inverse(fz),
+        })
+    }
+
+    /// Perceptual difference between two linear colors using CIEDE2000.
+    /// Around 1.0 is the smallest difference most people notice.
+    pub fn delta_e(self, other: Color) -> f64 {
+        self.to_lab().delta_e(other.to_lab())
+    }
+}
+
+impl Lab {
+    /// The CIE76 difference: Euclidean distance in Lab.
+    pub fn delta_e76(self, other: Lab) -> f64 {
+        ((self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2))
+            .sqrt()
+    }
+
+    /// The CIEDE2000 difference, following Sharma, Wu and Dalal's notes.
+    pub fn delta_e(self, other: Lab) -> f64 {
+        let c1 = self.a.hypot(self.b);
+        let c2 = other.a.hypot(other.b);
+        let c_mean = (c1 + c2) / 2.0;
+
+        let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+        let a1 = self.a * (1.0 + g);
+        let a2 = other.a * (1.0 + g);
+        let c1 = a1.hypot(self.b);
+        let c2 = a2.hypot(other.b);
+
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let h1 = hue(self.b, a1);
+        let h2 = hue(other.b, a2);
+
+        let delta_l = other.l - self.l;
+        let delta_c = c2 - c1;
+        let delta_h = if c1 * c2 == 0.0 {
+            0.0
+        } else if (h2 - h1).abs() <= 180.0 {
+            h2 - h1
+        } else if h2 <= h1 {
+            h2 - h1 + 360.0
+        } else {
+            h2 - h1 - 360.0
+        };
+        let delta_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+        let l_mean = (self.l + other.l) / 2.0;
+        let c_mean = (c1 + c2) / 2.0;
+        let h_mean = if c1 * c2 == 0.0 {
+            h1 + h2
+        } else if (h1 - h2).abs() <= 180.0 {
+            (h1 + h2) / 2.0
+        } else if h1 + h2 < 360.0 {
+            (h1 + h2 + 360.0) / 2.0
+        } else {
+            (h1 + h2 - 360.0) / 2.0
+        };
+
+        let t = 1.0 - 0.17 * (h_mean - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * h_mean).to_radians().cos()
+            + 0.32 * (3.0 * h_mean + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * h_mean - 63.0).to_radians().cos();
+
+        let l_offset = (l_mean - 50.0).powi(2);
+        let s_l = 1.0 + 0.015 * l_offset / (20.0 + l_offset).sqrt();
+        let s_c = 1.0 + 0.045 * c_mean;
+        let s_h = 1.0 + 0.015 * c_mean * t;
+
+        let theta = 30.0 * (-((h_mean - 275.0) / 25.0).powi(2)).exp();
+        let r_c = 2.0 * (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt();
+        let r_t = -r_c * (2.0 * theta).to_radians().sin();
+
+        let l = delta_l / s_l;
+        let c = delta_c / s_c;
+        let h = delta_h / s_h;
+
+        (l * l + c * c + h * h + r_t * c * h).sqrt()
+    }
+}
+
+impl Canvas {
+    /// The mean CIEDE2000 difference between this canvas and `reference`,
+    /// for checking how far a render has drifted. Panics if the sizes
+    /// differ.
+    pub fn delta_e(&self, reference: &Canvas) -> f64 {
+        assert!(
+            self.width == reference.width && self.height == reference.height,
+            "canvas is {}x{} but the reference is {}x{}",
+            self.width,
+            self.height,
+            reference.width,
+            reference.height
+        );
+
+        if self.data.is_empty() {
+            return 0.0;
+        }
+
+        let total: f64 = self
+            .pixels()
+            .zip(reference.pixels())
+            .map(|(&a, &b)| a.delta_e(b))
+            .sum();
+
+        total / self.data.len() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    fn assert_colors_equal(a: Color, b: Color) {
+        assert!(
+            equal(a.red, b.red) && equal(a.green, b.green) && equal(a.blue, b.blue),
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn converting_to_and_from_hsv() {
+        let hsv = Color::new(1.0, 0.5, 0.0).to_hsv();
+        assert!(equal(hsv.hue, 30.0));
+        assert!(equal(hsv.saturation, 1.0));
+        assert!(equal(hsv.value, 1.0));
+
+        let hsv = Color::new(0.2, 0.4, 0.8).to_hsv();
+        assert!(equal(hsv.hue, 220.0));
+        assert!(equal(hsv.saturation, 0.75));
+        assert_colors_equal(Color::from_hsv(hsv), Color::new(0.2, 0.4, 0.8));
+
+        let gray = Color::new(0.5, 0.5, 0.5).to_hsv();
+        assert_eq!((gray.hue, gray.saturation), (0.0, 0.0));
+    }
+
+    #[test]
+    fn converting_to_and_from_hsl() {
+        let hsl = Color::new(0.2, 0.4, 0.8).to_hsl();
+        assert!(equal(hsl.hue, 220.0));
+        assert!(equal(hsl.saturation, 0.6));
+        assert!(equal(hsl.lightness, 0.5));
+
+        let magenta = Hsl {
+            hue: 300.0,
+            saturation: 1.0,
+            lightness: 0.5,
+        };
+        assert_colors_equal(Color::from_hsl(magenta), Color::new(1.0, 0.0, 1.0));
+
+        // hues wrap around
+        let red = Hsl {
+            hue: 360.0,
+            ..magenta
+        };
+        assert_colors_equal(Color::from_hsl(red), Color::new(1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn hue_round_trips() {
+        for i in 0..64 {
+            let color = Color::new(
+                (i % 4) as f64 / 3.0,
+                (i / 4 % 4) as f64 / 3.0,
+                (i / 16) as f64 / 3.0,
+            );
+
+            assert_colors_equal(Color::from_hsv(color.to_hsv()), color);
+            assert_colors_equal(Color::from_hsl(color.to_hsl()), color);
+        }
+    }
+
+    #[test]
+    fn converting_to_xyz_and_lab() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let xyz = white.to_xyz();
+        assert!(equal(xyz.x, WHITE.x) && equal(xyz.y, 1.0) && equal(xyz.z, WHITE.z));
+
+        let lab = white.to_lab();
+        assert!(equal(lab.l, 100.0) && equal(lab.a, 0.0) && equal(lab.b, 0.0));
+
+        let lab = Color::new(1.0, 0.0, 0.0).to_lab();
+        assert!((lab.l - 53.24).abs() < 0.01);
+        assert!((lab.a - 80.09).abs() < 0.01);
+        assert!((lab.b - 67.20).abs() < 0.01);
+
+        let color = Color::new(0.1, 0.6, 0.3);
+        assert_colors_equal(Color::from_xyz(color.to_xyz()), color);
+        assert_colors_equal(Color::from_lab(color.to_lab()), color);
+    }
+
+    #[test]
+    fn measuring_luminance() {
+        assert!(equal(Color::new(1.0, 1.0, 1.0).luminance(), 1.0));
+        assert!(equal(Color::new(0.0, 1.0, 0.0).luminance(), 0.71515));
+        assert!(equal(
+            Color::new(0.3, 0.2, 0.9).luminance(),
+            Color::new(0.3, 0.2, 0.9).to_xyz().y
+        ));
+    }
+
+    #[test]
+    fn computing_ciede2000() {
+        // pairs 1, 2 and 7 from Sharma, Wu and Dalal's test data
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+        ];
+
+        for ((l1, a1, b1), (l2, a2, b2), expected) in pairs {
+            let first = Lab {
+                l: l1,
+                a: a1,
+                b: b1,
+            };
+            let second = Lab {
+                l: l2,
+                a: a2,
+                b: b2,
+            };
+
+            assert!((first.delta_e(second) - expected).abs() < 1e-4);
+            assert!((second.delta_e(first) - expected).abs() < 1e-4);
+        }
+
+        let lab = Lab {
+            l: 50.0,
+            a: 0.0,
+            b: 0.0,
+        };
+        let other = Lab {
+            l: 53.0,
+            a: 4.0,
+            ..lab
+        };
+        assert!(equal(lab.delta_e76(other), 5.0));
+    }
+
+    #[test]
+    fn comparing_canvases() {
+        let mut render = Canvas::new(2, 1);
+        let reference = Canvas::new(2, 1);
+
+        assert_eq!(render.delta_e(&reference), 0.0);
+
+        render.write_pixel(0, 0, Color::new(1.0, 1.0, 1.0));
+        assert!(equal(render.delta_e(&reference), 50.0));
+    }
+}
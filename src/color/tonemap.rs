@@ -0,0 +1,141 @@
+use super::Color;
+use crate::canvas::Canvas;
+
+/// The white point John Hable used for the Uncharted 2 curve.
+pub const HABLE_WHITE: f64 = 11.2;
+
+/// Compresses unbounded linear radiance into `0.0..=1.0` so that highlights
+/// roll off instead of clipping. Every operator works per channel and
+/// treats negative values as black.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ToneMap {
+    /// Hard clip to `0.0..=1.0`, what writing an HDR canvas directly does.
+    Clamp,
+    /// `c / (1 + c)`, which never quite reaches white.
+    Reinhard,
+    /// Reinhard rescaled so that `white` maps exactly to 1.0.
+    ExtendedReinhard { white: f64 },
+    /// Krzysztof Narkowicz's fit of the ACES filmic reference curve.
+    Aces,
+    /// John Hable's Uncharted 2 filmic curve, normalized so that `white`
+    /// maps to 1.0. `HABLE_WHITE` is the usual choice.
+    Hable { white: f64 },
+}
+
+fn hable_partial(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
+
+impl ToneMap {
+    /// Maps one linear channel into `0.0..=1.0`.
+    pub fn map(&self, value: f64) -> f64 {
+        let x = value.max(0.0);
+        let mapped = match self {
+            ToneMap::Clamp => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::ExtendedReinhard { white } => x * (1.0 + x / (white * white)) / (1.0 + x),
+            ToneMap::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMap::Hable { white } => hable_partial(x) / hable_partial(*white),
+        };
+
+        mapped.clamp(0.0, 1.0)
+    }
+}
+
+impl Color {
+    /// Scales the color by `2^stops`, like opening a camera's aperture.
+    pub fn exposed(self, stops: f64) -> Color {
+        let scale = 2f64.powf(stops);
+        Color::new(self.red * scale, self.green * scale, self.blue * scale)
+    }
+
+    pub fn tone_map(self, operator: ToneMap) -> Color {
+        Color::new(
+            operator.map(self.red),
+            operator.map(self.green),
+            operator.map(self.blue),
+        )
+    }
+}
+
+impl Canvas {
+    /// Applies `exposure` (in stops) and then `operator` to every pixel,
+    /// giving a canvas ready to be quantized by an 8-bit image format.
+    pub fn tone_map(&self, exposure: f64, operator: ToneMap) -> Canvas {
+        self.map(|color| color.exposed(exposure).tone_map(operator))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    #[test]
+    fn mapping_with_reinhard() {
+        assert_eq!(ToneMap::Reinhard.map(0.0), 0.0);
+        assert_eq!(ToneMap::Reinhard.map(1.0), 0.5);
+        assert_eq!(ToneMap::Reinhard.map(-3.0), 0.0);
+        assert!(ToneMap::Reinhard.map(1000.0) < 1.0);
+
+        let extended = ToneMap::ExtendedReinhard { white: 4.0 };
+        assert!(equal(extended.map(1.0), 0.53125));
+        assert!(equal(extended.map(4.0), 1.0));
+        assert_eq!(extended.map(10.0), 1.0);
+    }
+
+    #[test]
+    fn mapping_with_filmic_curves() {
+        assert!(equal(ToneMap::Aces.map(0.0), 0.0));
+        assert!(equal(ToneMap::Aces.map(1.0), 0.80380));
+        assert_eq!(ToneMap::Aces.map(100.0), 1.0);
+
+        let hable = ToneMap::Hable { white: HABLE_WHITE };
+        assert!(equal(hable.map(0.0), 0.0));
+        assert!(equal(hable.map(HABLE_WHITE), 1.0));
+        assert!(hable.map(2.0) > hable.map(1.0));
+    }
+
+    #[test]
+    fn operators_are_monotonic() {
+        let operators = [
+            ToneMap::Clamp,
+            ToneMap::Reinhard,
+            ToneMap::ExtendedReinhard { white: 8.0 },
+            ToneMap::Aces,
+            ToneMap::Hable { white: HABLE_WHITE },
+        ];
+
+        for operator in operators {
+            let mut previous = 0.0;
+            for i in 0..200 {
+                let mapped = operator.map(i as f64 * 0.1);
+                assert!(mapped >= previous, "{:?} at {}", operator, i);
+                assert!((0.0..=1.0).contains(&mapped));
+                previous = mapped;
+            }
+        }
+    }
+
+    #[test]
+    fn exposing_and_tone_mapping_a_canvas() {
+        let mut canvas = Canvas::new(2, 1);
+        canvas.write_pixel(0, 0, Color::new(0.5, 1.5, 3.5));
+
+        assert_eq!(
+            Color::new(0.5, 1.0, 2.0).exposed(1.0),
+            Color::new(1.0, 2.0, 4.0)
+        );
+
+        let mapped = canvas.tone_map(1.0, ToneMap::Reinhard);
+        assert_eq!(mapped.pixel_at(0, 0), Color::new(0.5, 0.75, 0.875));
+        assert_eq!(mapped.pixel_at(1, 0), Color::new(0.0, 0.0, 0.0));
+    }
+}
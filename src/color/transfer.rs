@@ -0,0 +1,134 @@
+use super::Color;
+
+/// An opto-electronic transfer function, mapping linear light to the
+/// non-linear values stored in display-referred images.
+///
+/// Negative inputs are mirrored so the functions stay invertible for
+/// out-of-gamut colors.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Transfer {
+    /// Values are stored as linear light.
+    Linear,
+    /// The piecewise sRGB curve (IEC 61966-2-1).
+    Srgb,
+    /// The ITU-R BT.709 camera curve.
+    Rec709,
+    /// A pure power law, encoding `v` as `v^(1/gamma)`.
+    Gamma(f64),
+}
+
+impl Transfer {
+    /// Converts one linear channel to its encoded value.
+    pub fn encode(&self, linear: f64) -> f64 {
+        let v = linear.abs();
+        let encoded = match self {
+            Transfer::Linear => v,
+            Transfer::Srgb if v <= 0.003_130_8 => v * 12.92,
+            Transfer::Srgb => 1.055 * v.powf(1.0 / 2.4) - 0.055,
+            Transfer::Rec709 if v < 0.018 => v * 4.5,
+            Transfer::Rec709 => 1.099 * v.powf(0.45) - 0.099,
+            Transfer::Gamma(gamma) => v.powf(1.0 / gamma),
+        };
+
+        encoded.copysign(linear)
+    }
+
+    /// Converts one encoded channel back to linear light.
+    pub fn decode(&self, encoded: f64) -> f64 {
+        let v = encoded.abs();
+        let linear = match self {
+            Transfer::Linear => v,
+            Transfer::Srgb if v <= 0.040_45 => v / 12.92,
+            Transfer::Srgb => ((v + 0.055) / 1.055).powf(2.4),
+            Transfer::Rec709 if v < 0.081 => v / 4.5,
+            Transfer::Rec709 => ((v + 0.099) / 1.099).powf(1.0 / 0.45),
+            Transfer::Gamma(gamma) => v.powf(*gamma),
+        };
+
+        linear.copysign(encoded)
+    }
+}
+
+impl Color {
+    pub fn encode(self, transfer: Transfer) -> Color {
+        Color::new(
+            transfer.encode(self.red),
+            transfer.encode(self.green),
+            transfer.encode(self.blue),
+        )
+    }
+
+    pub fn decode(self, transfer: Transfer) -> Color {
+        Color::new(
+            transfer.decode(self.red),
+            transfer.decode(self.green),
+            transfer.decode(self.blue),
+        )
+    }
+
+    /// Encodes a linear color for display on an sRGB monitor.
+    pub fn to_srgb(self) -> Color {
+        self.encode(Transfer::Srgb)
+    }
+
+    /// Linearizes a color read from an sRGB image.
+    pub fn from_srgb(srgb: Color) -> Color {
+        srgb.decode(Transfer::Srgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    #[test]
+    fn encoding_srgb() {
+        assert_eq!(Transfer::Srgb.encode(0.0), 0.0);
+        assert!(equal(Transfer::Srgb.encode(1.0), 1.0));
+        assert!(equal(Transfer::Srgb.encode(0.002), 0.02584));
+        assert!(equal(Transfer::Srgb.encode(0.214), 0.49996));
+        assert!(equal(Transfer::Srgb.decode(0.5), 0.21404));
+        assert!(equal(Transfer::Srgb.encode(-0.214), -0.49996));
+    }
+
+    #[test]
+    fn encoding_rec709_and_gamma() {
+        assert!(equal(Transfer::Rec709.encode(0.01), 0.045));
+        assert!(equal(Transfer::Rec709.encode(0.18), 0.40901));
+        assert!(equal(Transfer::Gamma(2.2).encode(0.5), 0.72974));
+        assert_eq!(Transfer::Linear.encode(0.5), 0.5);
+    }
+
+    #[test]
+    fn transfers_round_trip() {
+        let transfers = [
+            Transfer::Linear,
+            Transfer::Srgb,
+            Transfer::Rec709,
+            Transfer::Gamma(1.8),
+        ];
+
+        for transfer in transfers {
+            for i in 0..=40 {
+                let value = i as f64 / 20.0 - 0.5;
+                assert!(
+                    (transfer.decode(transfer.encode(value)) - value).abs() < 1e-9,
+                    "{:?} at {}",
+                    transfer,
+                    value
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn converting_colors_to_and_from_srgb() {
+        let color = Color::new(0.0, 0.214, 1.0);
+        let srgb = color.to_srgb();
+
+        assert!(equal(srgb.green, 0.49996));
+        assert!(equal(srgb.blue, 1.0));
+        assert!(equal(Color::from_srgb(srgb).green, 0.214));
+    }
+}
@@ -0,0 +1,145 @@
+#![allow(dead_code)]
+use crate::spectrum::SampledWavelengths;
+
+/// Fraunhofer lines used to characterize glass, in nanometres.
+pub const FRAUNHOFER_D: f64 = 587.56;
+pub const FRAUNHOFER_F: f64 = 486.13;
+pub const FRAUNHOFER_C: f64 = 656.27;
+
+/// Schott N-BK7, the usual crown glass for prisms and lenses.
+pub const BK7: RefractiveIndex = RefractiveIndex::Sellmeier {
+    b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+    c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+};
+
+/// Fused quartz (Malitson, 1965).
+pub const FUSED_SILICA: RefractiveIndex = RefractiveIndex::Sellmeier {
+    b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+    c: [0.004_679_148, 0.013_512_063, 97.934_003],
+};
+
+/// How strongly a material bends light, possibly varying with wavelength.
+///
+/// The dispersive forms take wavelengths in micrometres internally, as the
+/// published coefficients do, but every method here takes nanometres.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RefractiveIndex {
+    Constant(f64),
+    /// `n = a + b/λ² + c/λ⁴`, with λ in µm.
+    Cauchy {
+        a: f64,
+        b: f64,
+        c: f64,
+    },
+    /// `n² = 1 + Σ bᵢλ²/(λ² - cᵢ)`, with λ in µm and `cᵢ` in µm².
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl RefractiveIndex {
+    /// A two-term Cauchy curve with index `n_d` at the sodium d line and
+    /// the given Abbe number; lower Abbe numbers disperse more.
+    pub fn cauchy_from_abbe(n_d: f64, abbe: f64) -> Self {
+        let inverse_square = |nm: f64| 1.0 / (nm / 1000.0).powi(2);
+        let b = (n_d - 1.0) / abbe / (inverse_square(FRAUNHOFER_F) - inverse_square(FRAUNHOFER_C));
+
+        RefractiveIndex::Cauchy {
+            a: n_d - b * inverse_square(FRAUNHOFER_D),
+            b,
+            c: 0.0,
+        }
+    }
+
+    /// The index at `lambda` nanometres.
+    pub fn at(&self, lambda: f64) -> f64 {
+        let um2 = (lambda / 1000.0).powi(2);
+
+        match *self {
+            RefractiveIndex::Constant(n) => n,
+            RefractiveIndex::Cauchy { a, b, c } => a + b / um2 + c / (um2 * um2),
+            RefractiveIndex::Sellmeier { b, c } => {
+                let sum: f64 = b.iter().zip(c).map(|(b, c)| b * um2 / (um2 - c)).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, RefractiveIndex::Constant(_))
+    }
+
+    /// `(n_d - 1) / (n_F - n_C)`; infinite for a constant index.
+    pub fn abbe_number(&self) -> f64 {
+        match self {
+            RefractiveIndex::Constant(_) => f64::INFINITY,
+            _ => (self.at(FRAUNHOFER_D) - 1.0) / (self.at(FRAUNHOFER_F) - self.at(FRAUNHOFER_C)),
+        }
+    }
+
+    /// The index for the hero wavelength of a spectral path. A dispersive
+    /// interface sends each wavelength in a different direction, so the
+    /// companion wavelengths are terminated and only the hero continues.
+    pub fn for_wavelengths(&self, wavelengths: &mut SampledWavelengths) -> f64 {
+        if self.is_dispersive() {
+            wavelengths.terminate_secondary();
+        }
+
+        self.at(wavelengths.lambda[0])
+    }
+}
+
+impl From<f64> for RefractiveIndex {
+    fn from(n: f64) -> Self {
+        RefractiveIndex::Constant(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluating_sellmeier_glasses() {
+        assert!((BK7.at(FRAUNHOFER_D) - 1.5168).abs() < 1e-4);
+        assert!((BK7.abbe_number() - 64.17).abs() < 0.05);
+        assert!((FUSED_SILICA.at(FRAUNHOFER_D) - 1.4585).abs() < 1e-4);
+        assert!((FUSED_SILICA.abbe_number() - 67.8).abs() < 0.1);
+    }
+
+    #[test]
+    fn blue_bends_more_than_red() {
+        for glass in [
+            BK7,
+            FUSED_SILICA,
+            RefractiveIndex::cauchy_from_abbe(1.5, 40.0),
+        ] {
+            assert!(glass.at(450.0) > glass.at(550.0));
+            assert!(glass.at(550.0) > glass.at(650.0));
+        }
+    }
+
+    #[test]
+    fn fitting_cauchy_to_an_abbe_number() {
+        let flint = RefractiveIndex::cauchy_from_abbe(1.62, 36.4);
+
+        assert!((flint.at(FRAUNHOFER_D) - 1.62).abs() < 1e-12);
+        assert!((flint.abbe_number() - 36.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn constant_indices_keep_every_wavelength() {
+        let water = RefractiveIndex::from(1.333);
+        let mut wavelengths = SampledWavelengths::sample_uniform(0.25);
+
+        assert_eq!(water.for_wavelengths(&mut wavelengths), 1.333);
+        assert!(!wavelengths.secondary_terminated());
+        assert_eq!(water.abbe_number(), f64::INFINITY);
+        assert_eq!(RefractiveIndex::Constant(1.0).abbe_number(), f64::INFINITY);
+
+        let n = BK7.for_wavelengths(&mut wavelengths);
+        assert_eq!(n, BK7.at(wavelengths.lambda[0]));
+        assert!(wavelengths.secondary_terminated());
+    }
+}
@@ -0,0 +1,165 @@
+#![allow(dead_code)]
+use crate::canvas::Canvas;
+
+/// The per-pixel data held by a layer.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Plane {
+    /// Three channels, e.g. beauty, albedo or normals.
+    Color(Canvas),
+    /// One value per pixel in row-major order, e.g. depth.
+    Scalar(Vec<f64>),
+}
+
+/// A named plane of a `FrameBuffer`.
+///
+/// Color layers expose the channels `<name>.R`, `<name>.G` and `<name>.B`,
+/// or plain `R`, `G` and `B` for the unnamed beauty layer. A scalar layer is
+/// a single channel called `name`, so a depth pass is usually added as `Z`
+/// or `depth.Z`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Layer {
+    pub name: String,
+    pub plane: Plane,
+}
+
+impl Layer {
+    pub fn channel_names(&self) -> Vec<String> {
+        match &self.plane {
+            Plane::Color(_) if self.name.is_empty() => vec!["R".into(), "G".into(), "B".into()],
+            Plane::Color(_) => ["R", "G", "B"]
+                .iter()
+                .map(|c| format!("{}.{}", self.name, c))
+                .collect(),
+            Plane::Scalar(_) => vec![self.name.clone()],
+        }
+    }
+
+    /// Returns channel `channel` (in `channel_names` order) of the pixel at
+    /// row-major index `pixel`.
+    pub fn sample(&self, channel: usize, pixel: usize) -> f64 {
+        match &self.plane {
+            Plane::Color(canvas) => {
+                let color = &canvas.data[pixel];
+                [color.red, color.green, color.blue][channel]
+            }
+            Plane::Scalar(values) => values[pixel],
+        }
+    }
+}
+
+/// Several same-sized render outputs (AOVs) kept together so they can be
+/// written to one file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FrameBuffer {
+    pub width: usize,
+    pub height: usize,
+    pub layers: Vec<Layer>,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            layers: vec![],
+        }
+    }
+
+    /// A frame buffer holding `canvas` as its unnamed beauty layer.
+    pub fn from_canvas(canvas: Canvas) -> Self {
+        let mut frame = Self::new(canvas.width, canvas.height);
+        frame.add_color_layer("", canvas);
+        frame
+    }
+
+    /// Adds a color layer, replacing any layer with the same name.
+    /// Panics if the canvas size doesn't match the frame buffer.
+    pub fn add_color_layer(&mut self, name: &str, canvas: Canvas) {
+        assert!(
+            canvas.width == self.width && canvas.height == self.height,
+            "layer {:?} is {}x{} but the frame buffer is {}x{}",
+            name,
+            canvas.width,
+            canvas.height,
+            self.width,
+            self.height
+        );
+
+        self.insert(name, Plane::Color(canvas));
+    }
+
+    /// Adds a scalar layer, replacing any layer with the same name.
+    /// Panics unless there is exactly one value per pixel.
+    pub fn add_scalar_layer(&mut self, name: &str, values: Vec<f64>) {
+        assert_eq!(
+            values.len(),
+            self.width * self.height,
+            "layer {:?} needs one value per pixel",
+            name
+        );
+
+        self.insert(name, Plane::Scalar(values));
+    }
+
+    fn insert(&mut self, name: &str, plane: Plane) {
+        let layer = Layer {
+            name: name.to_string(),
+            plane,
+        };
+
+        match self.layers.iter_mut().find(|l| l.name == name) {
+            Some(existing) => *existing = layer,
+            None => self.layers.push(layer),
+        }
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&Layer> {
+        self.layers.iter().find(|l| l.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn naming_layer_channels() {
+        let mut frame = FrameBuffer::from_canvas(Canvas::new(2, 1));
+        frame.add_color_layer("normal", Canvas::new(2, 1));
+        frame.add_scalar_layer("depth.Z", vec![1.0, 2.0]);
+
+        let names: Vec<Vec<String>> = frame.layers.iter().map(|l| l.channel_names()).collect();
+
+        assert_eq!(
+            names,
+            vec![
+                vec!["R", "G", "B"],
+                vec!["normal.R", "normal.G", "normal.B"],
+                vec!["depth.Z"],
+            ]
+        );
+    }
+
+    #[test]
+    fn sampling_and_replacing_layers() {
+        let mut albedo = Canvas::new(2, 1);
+        albedo.write_pixel(1, 0, Color::new(0.1, 0.2, 0.3));
+
+        let mut frame = FrameBuffer::new(2, 1);
+        frame.add_color_layer("albedo", albedo);
+        frame.add_scalar_layer("Z", vec![5.0, 6.0]);
+        frame.add_scalar_layer("Z", vec![7.0, 8.0]);
+
+        assert_eq!(frame.layers.len(), 2);
+        assert_eq!(frame.layer("albedo").unwrap().sample(2, 1), 0.3);
+        assert_eq!(frame.layer("Z").unwrap().sample(0, 1), 8.0);
+        assert!(frame.layer("normal").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "layer \"normal\" is 3x1 but the frame buffer is 2x1")]
+    fn rejecting_mismatched_layers() {
+        FrameBuffer::new(2, 1).add_color_layer("normal", Canvas::new(3, 1));
+    }
+}
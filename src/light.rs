@@ -0,0 +1,21 @@
+#![allow(dead_code)]
+use crate::tuple::Tuple;
+
+pub mod area;
+pub mod directional;
+pub mod environment;
+pub mod sky;
+pub mod spot;
+
+/// Two unit vectors perpendicular to the unit vector `n` and to each other
+/// (Duff et al., 2017).
+fn orthonormal_basis(n: Tuple) -> (Tuple, Tuple) {
+    let sign = 1.0f64.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+
+    (
+        Tuple::to_vector(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Tuple::to_vector(b, sign + n.y * n.y * a, -n.y),
+    )
+}
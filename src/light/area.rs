@@ -0,0 +1,246 @@
+use super::orthonormal_basis;
+use crate::color::Color;
+use crate::random::{uniform_disk, Pcg32};
+use crate::tuple::Tuple;
+
+/// The emitting surface of an area light.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AreaShape {
+    /// The parallelogram spanned by the vectors `u` and `v` from `corner`.
+    Rectangle { corner: Tuple, u: Tuple, v: Tuple },
+    /// A disk of `radius` around `center`, facing along `normal`.
+    Disk {
+        center: Tuple,
+        normal: Tuple,
+        radius: f64,
+    },
+}
+
+/// A light with a surface, casting soft shadows.
+///
+/// The surface is split into a k×k grid of cells and each shadow test
+/// casts one ray per cell, so a point that sees part of the light is
+/// partly lit. With `jitter` each ray aims at a random spot in its cell,
+/// trading banding for noise; without it every ray aims at the cell
+/// center.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct AreaLight {
+    pub shape: AreaShape,
+    pub intensity: Color,
+    /// Rays per shadow test, rounded up to a square number.
+    pub samples: usize,
+    pub jitter: bool,
+}
+
+const DEFAULT_SAMPLES: usize = 16;
+
+impl AreaLight {
+    pub fn rectangle(corner: Tuple, u: Tuple, v: Tuple, intensity: Color) -> Self {
+        Self::new(AreaShape::Rectangle { corner, u, v }, intensity)
+    }
+
+    pub fn disk(center: Tuple, normal: Tuple, radius: f64, intensity: Color) -> Self {
+        let normal = normal.normalize();
+        Self::new(
+            AreaShape::Disk {
+                center,
+                normal,
+                radius,
+            },
+            intensity,
+        )
+    }
+
+    fn new(shape: AreaShape, intensity: Color) -> Self {
+        Self {
+            shape,
+            intensity,
+            samples: DEFAULT_SAMPLES,
+            jitter: true,
+        }
+    }
+
+    pub fn center(&self) -> Tuple {
+        match self.shape {
+            AreaShape::Rectangle { corner, u, v } => corner + u * 0.5 + v * 0.5,
+            AreaShape::Disk { center, .. } => center,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        match self.shape {
+            AreaShape::Rectangle { u, v, .. } => u.cross(&v).magnitude(),
+            AreaShape::Disk { radius, .. } => std::f64::consts::PI * radius * radius,
+        }
+    }
+
+    /// The point at `(s, t)` in `0.0..1.0` on the surface. Disks use the
+    /// concentric mapping, so grid cells stay compact.
+    pub fn point_on_light(&self, s: f64, t: f64) -> Tuple {
+        match self.shape {
+            AreaShape::Rectangle { corner, u, v } => corner + u * s + v * t,
+            AreaShape::Disk {
+                center,
+                normal,
+                radius,
+            } => {
+                let (tangent, bitangent) = orthonormal_basis(normal);
+                let (x, y) = uniform_disk(s, t);
+                center + tangent * (x * radius) + bitangent * (y * radius)
+            }
+        }
+    }
+
+    /// One point in each cell of the sampling grid.
+    pub fn sample_points(&self, rng: &mut Pcg32) -> Vec<Tuple> {
+        let side = (self.samples.max(1) as f64).sqrt().ceil() as usize;
+
+        (0..side * side)
+            .map(|i| {
+                let (ds, dt) = if self.jitter {
+                    rng.next_2d()
+                } else {
+                    (0.5, 0.5)
+                };
+                let s = ((i % side) as f64 + ds) / side as f64;
+                let t = ((i / side) as f64 + dt) / side as f64;
+
+                self.point_on_light(s, t)
+            })
+            .collect()
+    }
+
+    /// How much of the light `point` can see, from 0.0 in full shadow to
+    /// 1.0 fully lit. `visible(point, sample)` reports whether nothing
+    /// blocks the segment between them.
+    pub fn shadow_factor<F>(&self, point: Tuple, rng: &mut Pcg32, visible: F) -> f64
+    where
+        F: Fn(Tuple, Tuple) -> bool,
+    {
+        let samples = self.sample_points(rng);
+        let lit = samples
+            .iter()
+            .filter(|&&sample| visible(point, sample))
+            .count();
+
+        lit as f64 / samples.len() as f64
+    }
+
+    /// The light's intensity at `point`, dimmed by the shadow factor.
+    pub fn intensity_at<F>(&self, point: Tuple, rng: &mut Pcg32, visible: F) -> Color
+    where
+        F: Fn(Tuple, Tuple) -> bool,
+    {
+        let factor = self.shadow_factor(point, rng, visible);
+        Color::new(
+            self.intensity.red * factor,
+            self.intensity.green * factor,
+            self.intensity.blue * factor,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    fn ceiling_panel() -> AreaLight {
+        AreaLight::rectangle(
+            Tuple::to_point(-1.0, 2.0, -1.0),
+            Tuple::to_vector(2.0, 0.0, 0.0),
+            Tuple::to_vector(0.0, 0.0, 2.0),
+            Color::new(1.0, 1.0, 1.0),
+        )
+    }
+
+    #[test]
+    fn describing_a_rectangle_light() {
+        let light = ceiling_panel();
+
+        assert_eq!(light.center(), Tuple::to_point(0.0, 2.0, 0.0));
+        assert!(equal(light.area(), 4.0));
+        assert_eq!(light.samples, 16);
+        assert!(light.jitter);
+    }
+
+    #[test]
+    fn sampling_cell_centers_without_jitter() {
+        let mut light = ceiling_panel();
+        light.samples = 4;
+        light.jitter = false;
+
+        let points = light.sample_points(&mut Pcg32::new(0, 0));
+        assert_eq!(
+            points,
+            vec![
+                Tuple::to_point(-0.5, 2.0, -0.5),
+                Tuple::to_point(0.5, 2.0, -0.5),
+                Tuple::to_point(-0.5, 2.0, 0.5),
+                Tuple::to_point(0.5, 2.0, 0.5),
+            ]
+        );
+    }
+
+    #[test]
+    fn jittered_samples_stay_in_their_cells() {
+        let mut light = ceiling_panel();
+        light.samples = 9;
+
+        let points = light.sample_points(&mut Pcg32::new(5, 0));
+        assert_eq!(points.len(), 9);
+        for (i, p) in points.iter().enumerate() {
+            let cell = (((p.x + 1.0) * 1.5) as usize, ((p.z + 1.0) * 1.5) as usize);
+            assert_eq!(cell, (i % 3, i / 3));
+            assert_eq!(p.y, 2.0);
+        }
+    }
+
+    #[test]
+    fn sampling_a_disk_light() {
+        let light = AreaLight::disk(
+            Tuple::to_point(0.0, 3.0, 0.0),
+            Tuple::to_vector(0.0, -2.0, 0.0),
+            0.5,
+            Color::new(1.0, 1.0, 1.0),
+        );
+
+        assert!(equal(light.area(), std::f64::consts::PI * 0.25));
+        for p in light.sample_points(&mut Pcg32::new(1, 1)) {
+            let offset = p - light.center();
+            assert!(equal(p.y, 3.0));
+            assert!(offset.magnitude() <= 0.5 + 1e-9);
+        }
+    }
+
+    #[test]
+    fn computing_shadow_factors() {
+        let mut light = ceiling_panel();
+        light.jitter = false;
+        let point = Tuple::to_point(0.0, 0.0, 0.0);
+        let mut rng = Pcg32::new(0, 0);
+
+        assert_eq!(light.shadow_factor(point, &mut rng, |_, _| true), 1.0);
+        assert_eq!(light.shadow_factor(point, &mut rng, |_, _| false), 0.0);
+
+        // a wall hides the half of the panel with negative x
+        let penumbra = light.shadow_factor(point, &mut rng, |_, sample| sample.x > 0.0);
+        assert_eq!(penumbra, 0.5);
+        assert_eq!(
+            light.intensity_at(point, &mut rng, |_, sample| sample.x > 0.0),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+
+    #[test]
+    fn jittered_shadows_converge() {
+        let mut light = ceiling_panel();
+        light.samples = 256;
+        let mut rng = Pcg32::new(3, 7);
+
+        // the wall now cuts the panel at x = 0.3, leaving 35% visible
+        let factor =
+            light.shadow_factor(Tuple::to_point(0.0, 0.0, 0.0), &mut rng, |_, s| s.x > 0.3);
+        assert!((factor - 0.35).abs() < 0.03, "{}", factor);
+    }
+}
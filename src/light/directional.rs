@@ -0,0 +1,184 @@
+use super::orthonormal_basis;
+use crate::color::Color;
+use crate::random::Pcg32;
+use crate::tuple::Tuple;
+
+/// The angular diameter of the sun seen from Earth, in radians.
+pub const SUN_ANGULAR_DIAMETER: f64 = 0.0093;
+
+/// A light infinitely far away, like the sun: every ray arrives parallel
+/// and without falloff.
+///
+/// With a nonzero `angular_diameter` the light covers a small disk of sky,
+/// and shadow tests aim `samples` rays into that cone, which softens
+/// shadows with distance from the occluder as real sunlight does.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DirectionalLight {
+    /// Unit vector along which the light travels.
+    pub direction: Tuple,
+    pub intensity: Color,
+    /// In radians; zero gives hard shadows.
+    pub angular_diameter: f64,
+    /// Rays per shadow test, rounded up to a square number.
+    pub samples: usize,
+    pub jitter: bool,
+}
+
+const DEFAULT_SAMPLES: usize = 16;
+
+impl DirectionalLight {
+    pub fn new(direction: Tuple, intensity: Color) -> Self {
+        Self {
+            direction: direction.normalize(),
+            intensity,
+            angular_diameter: 0.0,
+            samples: DEFAULT_SAMPLES,
+            jitter: true,
+        }
+    }
+
+    /// A light with the sun's angular size.
+    pub fn sun(direction: Tuple, intensity: Color) -> Self {
+        Self {
+            angular_diameter: SUN_ANGULAR_DIAMETER,
+            ..Self::new(direction, intensity)
+        }
+    }
+
+    /// The unit vector pointing back towards the light.
+    pub fn to_light(self) -> Tuple {
+        self.direction * -1.0
+    }
+
+    /// The direction towards the light through `(s, t)` in `0.0..1.0`,
+    /// uniformly covering the cone of the light's disk.
+    pub fn direction_in_cone(&self, s: f64, t: f64) -> Tuple {
+        let axis = self.to_light();
+        let cos_max = (self.angular_diameter / 2.0).cos();
+        let cos_theta = 1.0 - s * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * t;
+        let (tangent, bitangent) = orthonormal_basis(axis);
+
+        tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + axis * cos_theta
+    }
+
+    /// One direction towards the light per cell of the sampling grid, or
+    /// just `to_light` for a light without size.
+    pub fn sample_directions(&self, rng: &mut Pcg32) -> Vec<Tuple> {
+        if self.angular_diameter == 0.0 {
+            return vec![self.to_light()];
+        }
+
+        let side = (self.samples.max(1) as f64).sqrt().ceil() as usize;
+
+        (0..side * side)
+            .map(|i| {
+                let (ds, dt) = if self.jitter {
+                    rng.next_2d()
+                } else {
+                    (0.5, 0.5)
+                };
+                let s = ((i % side) as f64 + ds) / side as f64;
+                let t = ((i / side) as f64 + dt) / side as f64;
+
+                self.direction_in_cone(s, t)
+            })
+            .collect()
+    }
+
+    /// How much of the light reaches `point`, from 0.0 to 1.0.
+    /// `visible(point, direction)` reports whether a ray leaving `point`
+    /// along `direction` escapes the scene.
+    pub fn shadow_factor<F>(&self, point: Tuple, rng: &mut Pcg32, visible: F) -> f64
+    where
+        F: Fn(Tuple, Tuple) -> bool,
+    {
+        let directions = self.sample_directions(rng);
+        let lit = directions
+            .iter()
+            .filter(|&&direction| visible(point, direction))
+            .count();
+
+        lit as f64 / directions.len() as f64
+    }
+
+    /// The light's intensity at `point`, dimmed by the shadow factor.
+    pub fn intensity_at<F>(&self, point: Tuple, rng: &mut Pcg32, visible: F) -> Color
+    where
+        F: Fn(Tuple, Tuple) -> bool,
+    {
+        let factor = self.shadow_factor(point, rng, visible);
+        Color::new(
+            self.intensity.red * factor,
+            self.intensity.green * factor,
+            self.intensity.blue * factor,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+
+    fn noon() -> DirectionalLight {
+        DirectionalLight::new(Tuple::to_vector(0.0, -2.0, 0.0), Color::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn creating_a_directional_light() {
+        let light = noon();
+
+        assert_eq!(light.direction, Tuple::to_vector(0.0, -1.0, 0.0));
+        assert_eq!(light.to_light(), Tuple::to_vector(0.0, 1.0, 0.0));
+        assert_eq!(light.angular_diameter, 0.0);
+
+        let sun = DirectionalLight::sun(light.direction, light.intensity);
+        assert_eq!(sun.angular_diameter, SUN_ANGULAR_DIAMETER);
+    }
+
+    #[test]
+    fn point_sized_lights_cast_hard_shadows() {
+        let light = noon();
+        let mut rng = Pcg32::new(0, 0);
+        let point = Tuple::to_point(3.0, 0.0, -2.0);
+
+        assert_eq!(
+            light.sample_directions(&mut rng),
+            vec![Tuple::to_vector(0.0, 1.0, 0.0)]
+        );
+        assert_eq!(light.shadow_factor(point, &mut rng, |_, _| true), 1.0);
+        assert_eq!(
+            light.intensity_at(point, &mut rng, |_, _| false),
+            Color::new(0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn sampling_the_sun_disk() {
+        let mut light = noon();
+        light.angular_diameter = 0.2;
+        let cos_max = 0.1f64.cos();
+
+        let directions = light.sample_directions(&mut Pcg32::new(4, 2));
+        assert_eq!(directions.len(), 16);
+        for d in directions {
+            assert!(equal(d.magnitude(), 1.0));
+            assert!(d.dot(&light.to_light()) >= cos_max - 1e-12);
+        }
+    }
+
+    #[test]
+    fn soft_shadow_edges() {
+        let mut light = noon();
+        light.angular_diameter = 0.2;
+        light.samples = 256;
+        let mut rng = Pcg32::new(1, 0);
+
+        // an occluder edge straight overhead hides half the sun
+        let factor =
+            light.shadow_factor(Tuple::to_point(0.0, 0.0, 0.0), &mut rng, |_, d| d.x > 0.0);
+        assert!((factor - 0.5).abs() < 0.05, "{}", factor);
+    }
+}
@@ -0,0 +1,364 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::fmt;
+use std::path::Path;
+
+use crate::canvas::file::ImageError;
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::tuple::Tuple;
+
+#[derive(Debug)]
+pub enum EnvironmentError {
+    Image(ImageError),
+    /// The panorama has no pixels to light with.
+    Empty,
+}
+
+impl fmt::Display for EnvironmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvironmentError::Image(err) => write!(f, "{}", err),
+            EnvironmentError::Empty => write!(f, "environment map has no pixels"),
+        }
+    }
+}
+
+impl Error for EnvironmentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EnvironmentError::Image(err) => Some(err),
+            EnvironmentError::Empty => None,
+        }
+    }
+}
+
+impl From<ImageError> for EnvironmentError {
+    fn from(err: ImageError) -> Self {
+        EnvironmentError::Image(err)
+    }
+}
+
+/// A piecewise-constant distribution over `0.0..1.0`, one step per entry
+/// of `func`, sampled by inverting its CDF.
+#[derive(Debug, PartialEq, Clone)]
+struct Distribution {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    /// The mean of `func`.
+    integral: f64,
+}
+
+impl Distribution {
+    /// All-zero functions are sampled uniformly instead, so that sampling
+    /// a black map still works.
+    fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let integral = func.iter().sum::<f64>() / n;
+
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for (i, f) in func.iter().enumerate() {
+            let step = if integral > 0.0 {
+                f / n / integral
+            } else {
+                1.0 / n
+            };
+            cdf.push(cdf[i] + step);
+        }
+
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// The density of step `i`, relative to a uniform distribution.
+    fn density(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+
+    /// Maps `u` in `0.0..1.0` to a point of the distribution, returning the
+    /// point, its density and the step it fell in.
+    fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let i = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let offset = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+
+        let point = (i as f64 + offset) / self.func.len() as f64;
+        (point.min(1.0 - f64::EPSILON), self.density(i), i)
+    }
+}
+
+/// Image-based lighting from an equirectangular (latitude-longitude)
+/// panorama, usually a Radiance HDR or PFM capture. `open` can't read EXR
+/// files; convert them first.
+///
+/// +y is up. The top row of the image looks straight up, the bottom row
+/// straight down, and `u` runs once around the horizon starting at +x and
+/// turning towards +z.
+///
+/// Directions can be importance sampled in proportion to the map's
+/// luminance, so a small bright sun in the panorama is found by a
+/// handful of samples instead of thousands.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnvironmentMap {
+    pub image: Canvas,
+    /// Multiplies every lookup, to balance the map against other lights.
+    pub scale: f64,
+    /// One distribution over columns for each row.
+    columns: Vec<Distribution>,
+    /// The distribution over rows.
+    rows: Distribution,
+}
+
+impl EnvironmentMap {
+    /// Fails with `EnvironmentError::Empty` if `image` has no pixels.
+    pub fn new(image: Canvas) -> Result<Self, EnvironmentError> {
+        if image.width == 0 || image.height == 0 {
+            return Err(EnvironmentError::Empty);
+        }
+        let height = image.height;
+
+        // rows near the poles cover less of the sphere, so weight by sin θ
+        let columns: Vec<Distribution> = image
+            .rows()
+            .enumerate()
+            .map(|(y, row)| {
+                let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+                Distribution::new(
+                    row.iter()
+                        .map(|c| c.luminance().max(0.0) * sin_theta)
+                        .collect(),
+                )
+            })
+            .collect();
+        let rows = Distribution::new(columns.iter().map(|d| d.integral).collect());
+
+        Ok(Self {
+            image,
+            scale: 1.0,
+            columns,
+            rows,
+        })
+    }
+
+    /// Loads a panorama from disk; see `Canvas::open`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, EnvironmentError> {
+        Self::new(Canvas::open(path)?)
+    }
+
+    /// The texture coordinates a direction looks at.
+    pub fn direction_to_uv(direction: Tuple) -> (f64, f64) {
+        let direction = direction.normalize();
+        let phi = direction.z.atan2(direction.x).rem_euclid(2.0 * PI);
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    pub fn uv_to_direction(u: f64, v: f64) -> Tuple {
+        let (phi, theta) = (2.0 * PI * u, PI * v);
+        Tuple::to_vector(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        )
+    }
+
+    /// The radiance arriving from `direction`, blended between the four
+    /// nearest texels. Use it for rays that leave the scene.
+    pub fn radiance(&self, direction: Tuple) -> Color {
+        let (u, v) = Self::direction_to_uv(direction);
+        let color = self.image.sample_bilinear_wrapped(u, v);
+
+        Color::new(
+            color.red * self.scale,
+            color.green * self.scale,
+            color.blue * self.scale,
+        )
+    }
+
+    /// Picks a direction with probability roughly proportional to the
+    /// light arriving from it. Returns the direction and its density with
+    /// respect to solid angle.
+    pub fn sample(&self, u1: f64, u2: f64) -> (Tuple, f64) {
+        let (v, row_pdf, y) = self.rows.sample(u1);
+        let (u, column_pdf, _) = self.columns[y].sample(u2);
+
+        let sin_theta = (PI * v).sin();
+        let pdf = if sin_theta > 0.0 {
+            row_pdf * column_pdf / (2.0 * PI * PI * sin_theta)
+        } else {
+            0.0
+        };
+
+        (Self::uv_to_direction(u, v), pdf)
+    }
+
+    /// The density `sample` gives `direction`, for weighting it against
+    /// other strategies.
+    pub fn pdf(&self, direction: Tuple) -> f64 {
+        let (u, v) = Self::direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        let density = self.rows.density(y) * self.columns[y].density(x);
+
+        density / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Pcg32;
+    use crate::utils::equal;
+
+    fn uniform(width: usize, height: usize, color: Color) -> EnvironmentMap {
+        let mut image = Canvas::new(width, height);
+        image.fill(color);
+        EnvironmentMap::new(image).unwrap()
+    }
+
+    /// A dim map with one bright texel.
+    fn sun_map() -> EnvironmentMap {
+        let mut image = Canvas::new(32, 16);
+        image.fill(Color::new(0.1, 0.1, 0.1));
+        image.write_pixel(20, 5, Color::new(1000.0, 1000.0, 1000.0));
+        EnvironmentMap::new(image).unwrap()
+    }
+
+    #[test]
+    fn mapping_directions_to_the_panorama() {
+        let up = EnvironmentMap::direction_to_uv(Tuple::to_vector(0.0, 1.0, 0.0));
+        assert_eq!(up.1, 0.0);
+
+        let (u, v) = EnvironmentMap::direction_to_uv(Tuple::to_vector(0.0, 0.0, 2.0));
+        assert!(equal(u, 0.25) && equal(v, 0.5));
+
+        for (u, v) in [(0.1, 0.2), (0.6, 0.5), (0.95, 0.9)] {
+            let direction = EnvironmentMap::uv_to_direction(u, v);
+            let (u2, v2) = EnvironmentMap::direction_to_uv(direction);
+            assert!(equal(u, u2) && equal(v, v2));
+        }
+    }
+
+    #[test]
+    fn looking_up_radiance() {
+        let mut map = uniform(8, 4, Color::new(0.5, 1.0, 2.0));
+        assert_eq!(
+            map.radiance(Tuple::to_vector(1.0, 0.3, -0.2)),
+            Color::new(0.5, 1.0, 2.0)
+        );
+
+        map.scale = 2.0;
+        assert_eq!(
+            map.radiance(Tuple::to_vector(0.0, -1.0, 0.0)),
+            Color::new(1.0, 2.0, 4.0)
+        );
+    }
+
+    #[test]
+    fn blending_across_the_seam() {
+        let mut image = Canvas::new(2, 1);
+        image.write_pixel(1, 0, Color::new(1.0, 1.0, 1.0));
+        let map = EnvironmentMap::new(image).unwrap();
+
+        // +x sits on the seam between the last column and the first
+        let seam = map.radiance(Tuple::to_vector(1.0, 0.0, 0.0));
+        assert_eq!(seam, Color::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn sampling_towards_bright_texels() {
+        let map = sun_map();
+        let mut rng = Pcg32::new(11, 0);
+        let hits = (0..1000)
+            .filter(|_| {
+                let (direction, _) = map.sample(rng.next_f64(), rng.next_f64());
+                let (u, v) = EnvironmentMap::direction_to_uv(direction);
+                ((u * 32.0) as usize, (v * 16.0) as usize) == (20, 5)
+            })
+            .count();
+
+        // the texel holds nearly all of the map's energy
+        assert!(hits > 950, "{}", hits);
+    }
+
+    #[test]
+    fn sample_densities_match_pdf() {
+        let map = sun_map();
+        let mut rng = Pcg32::new(2, 9);
+
+        for _ in 0..100 {
+            let (direction, pdf) = map.sample(rng.next_f64(), rng.next_f64());
+            let expected = map.pdf(direction);
+            assert!(
+                (pdf - expected).abs() <= 1e-6 * expected,
+                "{} {}",
+                pdf,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn estimating_irradiance() {
+        // ∫ L dω over the sphere is 4π for a constant map of 1
+        let map = uniform(64, 32, Color::new(1.0, 1.0, 1.0));
+        let mut rng = Pcg32::new(5, 5);
+        let n = 4000;
+
+        let estimate = (0..n)
+            .map(|_| {
+                let (direction, pdf) = map.sample(rng.next_f64(), rng.next_f64());
+                map.radiance(direction).red / pdf
+            })
+            .sum::<f64>()
+            / n as f64;
+
+        assert!(
+            (estimate - 4.0 * PI).abs() < 0.02 * 4.0 * PI,
+            "{}",
+            estimate
+        );
+    }
+
+    #[test]
+    fn black_maps_sample_uniformly() {
+        let map = uniform(4, 2, Color::new(0.0, 0.0, 0.0));
+        let (direction, pdf) = map.sample(0.3, 0.7);
+
+        assert!(pdf.is_finite() && pdf > 0.0);
+        assert!(equal(direction.magnitude(), 1.0));
+    }
+
+    #[test]
+    fn rejecting_empty_panoramas() {
+        for (width, height) in [(0, 0), (0, 4), (4, 0)] {
+            assert!(matches!(
+                EnvironmentMap::new(Canvas::new(width, height)),
+                Err(EnvironmentError::Empty)
+            ));
+        }
+
+        assert!(matches!(
+            EnvironmentMap::open("missing-panorama.hdr"),
+            Err(EnvironmentError::Image(_))
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+
+use super::directional::DirectionalLight;
+use crate::color::space::Xyz;
+use crate::color::Color;
+use crate::tuple::Tuple;
+
+/// Below this height a view direction is treated as grazing the horizon,
+/// where the Perez formula breaks down.
+const HORIZON_EPSILON: f64 = 0.01;
+
+/// The clear-sky model of Preetham, Shirley and Smits (1999): the color of
+/// the sky in any direction for a given sun position and haziness.
+///
+/// +y is up. Radiance comes out as linear RGB in kcd/m², so a bright
+/// midday sky reaches values around 10; tone map with some exposure
+/// compensation. Directions below the horizon see the sky at the horizon.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sky {
+    /// Unit vector pointing towards the sun.
+    pub sun_direction: Tuple,
+    /// Atmospheric turbidity: 2 is a very clear sky, 10 a hazy one.
+    pub turbidity: f64,
+}
+
+/// The five Perez coefficients for one channel of the Yxy sky.
+#[derive(Debug, PartialEq, Clone, Copy)]
+struct Perez([f64; 5]);
+
+impl Perez {
+    fn luminance(t: f64) -> Self {
+        Perez([
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ])
+    }
+
+    fn x(t: f64) -> Self {
+        Perez([
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ])
+    }
+
+    fn y(t: f64) -> Self {
+        Perez([
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ])
+    }
+
+    /// The sky's relative brightness at zenith angle `theta`, `gamma`
+    /// radians away from the sun.
+    fn eval(&self, theta: f64, gamma: f64) -> f64 {
+        let [a, b, c, d, e] = self.0;
+        (1.0 + a * (b / theta.cos()).exp())
+            * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    }
+}
+
+/// Evaluates a cubic in the sun's zenith angle for the zenith
+/// chromaticity.
+fn zenith_chromaticity(t: f64, theta: f64, coefficients: [[f64; 4]; 3]) -> f64 {
+    let powers = [theta.powi(3), theta.powi(2), theta, 1.0];
+    let row = |r: [f64; 4]| r.iter().zip(powers).map(|(c, p)| c * p).sum::<f64>();
+
+    t * t * row(coefficients[0]) + t * row(coefficients[1]) + row(coefficients[2])
+}
+
+impl Sky {
+    pub fn new(sun_direction: Tuple, turbidity: f64) -> Self {
+        Self {
+            sun_direction: sun_direction.normalize(),
+            turbidity,
+        }
+    }
+
+    /// The sun's zenith angle, kept just above the horizon.
+    fn sun_theta(&self) -> f64 {
+        self.sun_direction.y.clamp(HORIZON_EPSILON, 1.0).acos()
+    }
+
+    /// Moves a unit direction below the horizon up to the horizon, keeping
+    /// its compass heading. Straight down has no heading and looks towards
+    /// the sun instead.
+    fn above_horizon(&self, direction: Tuple) -> Tuple {
+        if direction.y >= HORIZON_EPSILON {
+            return direction;
+        }
+
+        let mut heading = Tuple::to_vector(direction.x, 0.0, direction.z);
+        if heading.magnitude() < 1e-9 {
+            heading = Tuple::to_vector(self.sun_direction.x, 0.0, self.sun_direction.z);
+        }
+        if heading.magnitude() < 1e-9 {
+            heading = Tuple::to_vector(0.0, 0.0, 1.0);
+        }
+
+        let horizontal = (1.0 - HORIZON_EPSILON * HORIZON_EPSILON).sqrt();
+        let heading = heading.normalize() * horizontal;
+        Tuple::to_vector(heading.x, HORIZON_EPSILON, heading.z)
+    }
+
+    /// The luminance and chromaticity straight up.
+    fn zenith(&self) -> (f64, f64, f64) {
+        let t = self.turbidity;
+        let theta = self.sun_theta();
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let x = zenith_chromaticity(
+            t,
+            theta,
+            [
+                [0.00166, -0.00375, 0.00209, 0.0],
+                [-0.02903, 0.06377, -0.03202, 0.00394],
+                [0.11693, -0.21196, 0.06052, 0.25886],
+            ],
+        );
+        let y = zenith_chromaticity(
+            t,
+            theta,
+            [
+                [0.00275, -0.00610, 0.00317, 0.0],
+                [-0.04214, 0.08970, -0.04153, 0.00516],
+                [0.15346, -0.26756, 0.06670, 0.26688],
+            ],
+        );
+
+        (luminance, x, y)
+    }
+
+    /// The sky radiance seen looking along `direction`, for the background
+    /// of rays that leave the scene.
+    pub fn radiance(&self, direction: Tuple) -> Color {
+        let direction = self.above_horizon(direction.normalize());
+        let theta = direction.y.acos();
+        let sun_theta = self.sun_theta();
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+
+        let t = self.turbidity;
+        let (zenith_luminance, zenith_x, zenith_y) = self.zenith();
+        let channel = |perez: Perez, zenith: f64| {
+            zenith * perez.eval(theta, gamma) / perez.eval(0.0, sun_theta)
+        };
+
+        let luminance = channel(Perez::luminance(t), zenith_luminance).max(0.0);
+        let x = channel(Perez::x(t), zenith_x);
+        let y = channel(Perez::y(t), zenith_y);
+
+        Color::from_xyz(Xyz {
+            x: x / y * luminance,
+            y: luminance,
+            z: (1.0 - x - y) / y * luminance,
+        })
+    }
+
+    /// A directional light shining from the sun's position, for the direct
+    /// sunlight the sky model itself leaves out.
+    pub fn sun(&self, intensity: Color) -> DirectionalLight {
+        DirectionalLight::sun(self.sun_direction * -1.0, intensity)
+    }
+
+    /// Whether the sun is above the horizon.
+    pub fn is_day(&self) -> bool {
+        self.sun_direction.y > 0.0
+    }
+}
+
+/// The sun direction for an elevation above the horizon and an azimuth
+/// around +y, both in radians, with azimuth 0 towards +z.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Tuple {
+    let theta = FRAC_PI_2 - elevation;
+    Tuple::to_vector(
+        theta.sin() * azimuth.sin(),
+        theta.cos(),
+        theta.sin() * azimuth.cos(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::directional::SUN_ANGULAR_DIAMETER;
+    use crate::utils::equal;
+
+    fn afternoon() -> Sky {
+        Sky::new(sun_direction(0.5, 0.0), 3.0)
+    }
+
+    #[test]
+    fn placing_the_sun() {
+        let overhead = sun_direction(FRAC_PI_2, 1.0);
+        assert!(equal(overhead.y, 1.0));
+
+        let east = sun_direction(0.0, FRAC_PI_2);
+        assert!(equal(east.x, 1.0) && equal(east.y, 0.0));
+
+        assert!(afternoon().is_day());
+        assert!(!Sky::new(sun_direction(-0.2, 0.0), 3.0).is_day());
+    }
+
+    #[test]
+    fn the_zenith_matches_the_model() {
+        let sky = afternoon();
+        let (luminance, x, y) = sky.zenith();
+        let zenith = sky.radiance(Tuple::to_vector(0.0, 1.0, 0.0)).to_xyz();
+
+        assert!(equal(zenith.y, luminance));
+        assert!(equal(zenith.x / (zenith.x + zenith.y + zenith.z), x));
+        assert!(equal(zenith.y / (zenith.x + zenith.y + zenith.z), y));
+        assert!(luminance > 1.0 && luminance < 20.0);
+    }
+
+    #[test]
+    fn clear_skies_are_blue() {
+        let sky = afternoon();
+        let color = sky.radiance(sun_direction(1.2, std::f64::consts::PI));
+
+        assert!(color.blue > color.green && color.green > color.red);
+    }
+
+    #[test]
+    fn brightening_towards_the_sun_and_horizon() {
+        let sky = afternoon();
+        let luminance = |direction| sky.radiance(direction).luminance();
+
+        // near the sun versus the same height opposite it
+        let near_sun = luminance(sun_direction(0.6, 0.1));
+        let away = luminance(sun_direction(0.6, std::f64::consts::PI));
+        assert!(near_sun > 2.0 * away);
+
+        // away from the sun the sky brightens towards the horizon
+        assert!(luminance(sun_direction(0.1, 2.0)) > luminance(sun_direction(1.0, 2.0)));
+
+        // below the horizon holds the horizon's value
+        assert_eq!(
+            sky.radiance(Tuple::to_vector(0.0, -0.5, 1.0)),
+            sky.radiance(Tuple::to_vector(0.0, 0.0, 1.0))
+        );
+    }
+
+    #[test]
+    fn hazier_skies_are_whiter() {
+        let direction = sun_direction(1.0, std::f64::consts::PI);
+        let saturation = |sky: Sky| sky.radiance(direction).to_hsv().saturation;
+
+        assert!(
+            saturation(Sky::new(sun_direction(0.5, 0.0), 2.0))
+                > saturation(Sky::new(sun_direction(0.5, 0.0), 8.0))
+        );
+    }
+
+    #[test]
+    fn pairing_the_sky_with_a_sun() {
+        let sky = afternoon();
+        let sun = sky.sun(Color::new(1.0, 1.0, 1.0));
+
+        assert_eq!(sun.to_light(), sky.sun_direction);
+        assert_eq!(sun.angular_diameter, SUN_ANGULAR_DIAMETER);
+    }
+}
@@ -0,0 +1,199 @@
+use super::orthonormal_basis;
+use crate::canvas::Canvas;
+use crate::color::Color;
+use crate::tuple::Tuple;
+
+/// A light at a point shining into a cone. Full intensity inside the inner
+/// angle fades smoothly to nothing at the outer angle.
+///
+/// A gobo, if set, is projected across the cone like a slide: the canvas
+/// fills the square that circumscribes the outer cone, centered on
+/// `direction`, and tints the light by the color it samples. An empty
+/// gobo blocks the light entirely.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpotLight {
+    pub position: Tuple,
+    /// Unit vector along the cone's axis.
+    pub direction: Tuple,
+    /// Half-angles in radians, with `inner <= outer`.
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub intensity: Color,
+    pub gobo: Option<Canvas>,
+}
+
+/// Hermite interpolation from 0 at `edge0` to 1 at `edge1`.
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    if edge0 == edge1 {
+        return if x < edge0 { 0.0 } else { 1.0 };
+    }
+
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl SpotLight {
+    pub fn new(
+        position: Tuple,
+        direction: Tuple,
+        inner_angle: f64,
+        outer_angle: f64,
+        intensity: Color,
+    ) -> Self {
+        Self {
+            position,
+            direction: direction.normalize(),
+            inner_angle: inner_angle.min(outer_angle),
+            outer_angle,
+            intensity,
+            gobo: None,
+        }
+    }
+
+    /// The unit vector from the light towards `point`.
+    fn direction_to(&self, point: Tuple) -> Tuple {
+        (point - self.position).normalize()
+    }
+
+    /// How much of the light reaches `point` given only the cone: 1.0 inside
+    /// the inner angle, 0.0 beyond the outer one.
+    pub fn falloff(&self, point: Tuple) -> f64 {
+        let cos_theta = self.direction_to(point).dot(&self.direction);
+        smoothstep(self.outer_angle.cos(), self.inner_angle.cos(), cos_theta)
+    }
+
+    /// The gobo coordinates `(u, v)` that `point` falls on, or `None` for
+    /// points behind the light.
+    pub fn gobo_coordinates(&self, point: Tuple) -> Option<(f64, f64)> {
+        let to_point = self.direction_to(point);
+        let forward = to_point.dot(&self.direction);
+        if forward <= 0.0 {
+            return None;
+        }
+
+        let (right, up) = orthonormal_basis(self.direction);
+        let scale = self.outer_angle.tan();
+        let x = to_point.dot(&right) / forward / scale;
+        let y = to_point.dot(&up) / forward / scale;
+
+        // image rows run downwards
+        Some((0.5 + 0.5 * x, 0.5 - 0.5 * y))
+    }
+
+    /// The light arriving at `point`, shaped by the cone and the gobo.
+    pub fn intensity_at(&self, point: Tuple) -> Color {
+        let falloff = self.falloff(point);
+        if falloff == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+
+        let light = Color::new(
+            self.intensity.red * falloff,
+            self.intensity.green * falloff,
+            self.intensity.blue * falloff,
+        );
+
+        match (&self.gobo, self.gobo_coordinates(point)) {
+            (Some(gobo), Some((u, v))) => light * gobo.sample_bilinear(u, v),
+            _ => light,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::equal;
+    use std::f64::consts::FRAC_PI_4;
+
+    /// A spot one unit above the origin, pointing straight down.
+    fn overhead(inner: f64, outer: f64) -> SpotLight {
+        SpotLight::new(
+            Tuple::to_point(0.0, 1.0, 0.0),
+            Tuple::to_vector(0.0, -3.0, 0.0),
+            inner,
+            outer,
+            Color::new(1.0, 1.0, 1.0),
+        )
+    }
+
+    /// The floor point at `angle` radians off the spot's axis.
+    fn floor_at(angle: f64) -> Tuple {
+        Tuple::to_point(angle.tan(), 0.0, 0.0)
+    }
+
+    #[test]
+    fn creating_a_spot_light() {
+        let spot = overhead(0.5, 0.3);
+
+        assert_eq!(spot.direction, Tuple::to_vector(0.0, -1.0, 0.0));
+        assert_eq!(spot.inner_angle, 0.3);
+        assert_eq!(spot.gobo, None);
+    }
+
+    #[test]
+    fn fading_across_the_cone() {
+        let spot = overhead(0.2, 0.4);
+
+        assert_eq!(spot.falloff(floor_at(0.0)), 1.0);
+        assert_eq!(spot.falloff(floor_at(0.19)), 1.0);
+        assert_eq!(spot.falloff(floor_at(0.41)), 0.0);
+        assert_eq!(spot.falloff(Tuple::to_point(0.0, 2.0, 0.0)), 0.0);
+
+        let inside = spot.falloff(floor_at(0.25));
+        let outside = spot.falloff(floor_at(0.35));
+        assert!(1.0 > inside && inside > outside && outside > 0.0);
+    }
+
+    #[test]
+    fn hard_edged_cones() {
+        let spot = overhead(0.3, 0.3);
+
+        assert_eq!(spot.intensity_at(floor_at(0.29)), Color::new(1.0, 1.0, 1.0));
+        assert_eq!(spot.intensity_at(floor_at(0.31)), Color::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn mapping_the_cone_onto_the_gobo() {
+        let spot = overhead(FRAC_PI_4, FRAC_PI_4);
+
+        let (u, v) = spot.gobo_coordinates(floor_at(0.0)).unwrap();
+        assert!(equal(u, 0.5) && equal(v, 0.5));
+
+        // the edge of the outer cone reaches the edge of the image
+        let (right, up) = orthonormal_basis(spot.direction);
+        let edge = spot.position + spot.direction + right;
+        let (u, v) = spot.gobo_coordinates(edge).unwrap();
+        assert!(equal(u, 1.0) && equal(v, 0.5));
+        let edge = spot.position + spot.direction + up;
+        let (u, v) = spot.gobo_coordinates(edge).unwrap();
+        assert!(equal(u, 0.5) && equal(v, 0.0));
+
+        assert_eq!(spot.gobo_coordinates(Tuple::to_point(0.0, 2.0, 0.0)), None);
+    }
+
+    #[test]
+    fn projecting_a_gobo() {
+        // left half red, right half blue
+        let mut gobo = Canvas::new(2, 1);
+        gobo.write_pixel(0, 0, Color::new(1.0, 0.0, 0.0));
+        gobo.write_pixel(1, 0, Color::new(0.0, 0.0, 1.0));
+
+        let mut spot = overhead(0.6, 0.6);
+        spot.gobo = Some(gobo);
+        let (right, _) = orthonormal_basis(spot.direction);
+
+        let left = spot.position + spot.direction - right * 0.5;
+        let right = spot.position + spot.direction + right * 0.5;
+        assert_eq!(spot.intensity_at(left), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(spot.intensity_at(right), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn empty_gobos_block_the_light() {
+        let mut spot = overhead(0.6, 0.6);
+        spot.gobo = Some(Canvas::new(0, 0));
+
+        assert_eq!(spot.intensity_at(floor_at(0.0)), Color::new(0.0, 0.0, 0.0));
+    }
+}
@@ -1,10 +1,17 @@
+mod antialias;
 mod canvas;
 mod color;
+mod dispersion;
+mod framebuffer;
+mod light;
 mod matrix;
+mod random;
+mod render;
+mod spectrum;
 mod tuple;
 mod utils;
 
-use std::{fs, io::Write};
+use std::process;
 
 use canvas::Canvas;
 use color::Color;
@@ -84,11 +91,8 @@ fn main() {
         }
     }
 
-    let mut file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open("sim.ppm")
-        .unwrap();
-
-    file.write_fmt(format_args!("{}", canvas.to_ppm())).unwrap();
+    if let Err(err) = canvas.save("sim.ppm") {
+        eprintln!("failed to save sim.ppm: {}", err);
+        process::exit(1);
+    }
 }
@@ -1,7 +1,5 @@
-use std::{
-    ops::{AddAssign, Mul},
-    process::Output,
-};
+#![allow(dead_code, unused_macros)]
+use std::ops::Mul;
 
 #[derive(Debug, Clone, PartialEq)]
 struct Matrix<T> {
@@ -59,8 +57,8 @@ where
             for c in 0..self.col {
                 let mut sum_intersection: T = T::default();
                 for i in 0..self.row {
-                    let intersection_one = self.at(r, i).unwrap().clone(); // * rhs.at(i, c).unwrap();
-                    let intersection_two = rhs.at(i, c).unwrap().clone();
+                    let intersection_one = *self.at(r, i).unwrap(); // * rhs.at(i, c).unwrap();
+                    let intersection_two = *rhs.at(i, c).unwrap();
                     let mul = intersection_one * intersection_two;
                     sum_intersection += mul;
                 }
@@ -0,0 +1,264 @@
+#![allow(dead_code)]
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::render::Tile;
+use crate::tuple::Tuple;
+
+const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+/// Marks tile keys so a tile never shares a stream with the pixel at its
+/// origin.
+const TILE_KEY: u64 = 1 << 63;
+
+/// The SplitMix64 finalizer, used to turn structured keys like pixel
+/// coordinates into well-spread seeds.
+pub fn mix(mut h: u64) -> u64 {
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// PCG32 (XSH RR): 64 bits of state, 32-bit output, and 2⁶³ independent
+/// streams selected by the increment.
+///
+/// Renders stay reproducible across thread counts as long as every pixel
+/// or tile draws from its own stream, via `for_pixel` or `for_tile`,
+/// rather than sharing one generator.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+impl Pcg32 {
+    pub fn new(seed: u64, stream: u64) -> Self {
+        let mut rng = Self {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    /// A generator for one pixel of a render seeded with `seed`.
+    pub fn for_pixel(seed: u64, x: usize, y: usize) -> Self {
+        Self::derive(seed, ((y as u64) << 32) | x as u64)
+    }
+
+    /// A generator for one tile, for work that is shared by its pixels.
+    pub fn for_tile(seed: u64, tile: &Tile) -> Self {
+        Self::derive(seed, TILE_KEY | ((tile.y as u64) << 32) | tile.x as u64)
+    }
+
+    fn derive(seed: u64, key: u64) -> Self {
+        Self::new(mix(seed ^ mix(key)), mix(key))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    /// A uniform value in `0.0..1.0` with the full 53 bits of precision.
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 32) | self.next_u32() as u64;
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub fn next_2d(&mut self) -> (f64, f64) {
+        (self.next_f64(), self.next_f64())
+    }
+}
+
+/// Maps the unit square to the unit disk with Shirley's concentric mapping,
+/// which keeps strata intact and distorts less than polar coordinates.
+pub fn uniform_disk(u: f64, v: f64) -> (f64, f64) {
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+
+    (r * theta.cos(), r * theta.sin())
+}
+
+/// A direction uniformly distributed over the unit sphere.
+pub fn uniform_sphere(u: f64, v: f64) -> Tuple {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    Tuple::to_vector(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_sphere_pdf() -> f64 {
+    1.0 / (4.0 * PI)
+}
+
+/// A direction uniformly distributed over the hemisphere around +z.
+pub fn uniform_hemisphere(u: f64, v: f64) -> Tuple {
+    let z = u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+
+    Tuple::to_vector(r * phi.cos(), r * phi.sin(), z)
+}
+
+pub fn uniform_hemisphere_pdf() -> f64 {
+    1.0 / (2.0 * PI)
+}
+
+/// A direction over the hemisphere around +z with density proportional to
+/// its cosine, by projecting a disk sample up onto the hemisphere.
+pub fn cosine_hemisphere(u: f64, v: f64) -> Tuple {
+    let (x, y) = uniform_disk(u, v);
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+    Tuple::to_vector(x, y, z)
+}
+
+/// The density of `cosine_hemisphere` for a direction whose z component
+/// is `cos_theta`.
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::render::Renderer;
+    use crate::utils::equal;
+
+    fn length(t: Tuple) -> f64 {
+        (t.x * t.x + t.y * t.y + t.z * t.z).sqrt()
+    }
+
+    fn grid(n: usize) -> impl Iterator<Item = (f64, f64)> {
+        (0..n * n).map(move |i| {
+            (
+                ((i % n) as f64 + 0.5) / n as f64,
+                ((i / n) as f64 + 0.5) / n as f64,
+            )
+        })
+    }
+
+    #[test]
+    fn matching_the_reference_pcg32() {
+        // the first outputs of pcg32-demo, seeded with 42 on stream 54
+        let mut rng = Pcg32::new(42, 54);
+        let outputs: Vec<u32> = (0..6).map(|_| rng.next_u32()).collect();
+
+        assert_eq!(
+            outputs,
+            vec![0xa15c02b7, 0x7b47f409, 0xba1d3330, 0x83d2f293, 0xbfa4784b, 0xcbed606e]
+        );
+    }
+
+    #[test]
+    fn generating_uniform_floats() {
+        let mut rng = Pcg32::new(7, 0);
+        let values: Vec<f64> = (0..10_000).map(|_| rng.next_f64()).collect();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+        assert!((mean - 0.5).abs() < 0.01);
+    }
+
+    #[test]
+    fn deriving_independent_streams() {
+        let first = |mut rng: Pcg32| rng.next_u32();
+        let tile = Tile {
+            x: 0,
+            y: 0,
+            width: 16,
+            height: 16,
+        };
+
+        assert_eq!(Pcg32::for_pixel(1, 3, 4), Pcg32::for_pixel(1, 3, 4));
+        assert_ne!(
+            first(Pcg32::for_pixel(1, 3, 4)),
+            first(Pcg32::for_pixel(1, 4, 3))
+        );
+        assert_ne!(
+            first(Pcg32::for_pixel(1, 3, 4)),
+            first(Pcg32::for_pixel(2, 3, 4))
+        );
+        assert_ne!(
+            first(Pcg32::for_pixel(1, 0, 0)),
+            first(Pcg32::for_tile(1, &tile))
+        );
+    }
+
+    #[test]
+    fn noisy_renders_are_reproducible_across_threads() {
+        let noise = |x: usize, y: usize| {
+            let mut rng = Pcg32::for_pixel(99, x, y);
+            Color::new(rng.next_f64(), rng.next_f64(), rng.next_f64())
+        };
+
+        let serial = Renderer::new(1, 16).render(23, 17, noise);
+        let parallel = Renderer::new(4, 3).render(23, 17, noise);
+        assert_eq!(serial, parallel);
+    }
+
+    #[test]
+    fn sampling_the_disk() {
+        assert_eq!(uniform_disk(0.5, 0.5), (0.0, 0.0));
+
+        let (x, y) = uniform_disk(1.0, 0.5);
+        assert!(equal(x, 1.0) && equal(y, 0.0));
+
+        assert!(grid(32).all(|(u, v)| {
+            let (x, y) = uniform_disk(u, v);
+            x * x + y * y <= 1.0
+        }));
+    }
+
+    #[test]
+    fn sampling_directions() {
+        for (u, v) in grid(16) {
+            for (direction, upper) in [
+                (uniform_sphere(u, v), false),
+                (uniform_hemisphere(u, v), true),
+                (cosine_hemisphere(u, v), true),
+            ] {
+                assert!(equal(length(direction), 1.0));
+                assert_eq!(direction.w, 0.0);
+                assert!(!upper || direction.z >= 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn sampled_directions_have_the_expected_mean() {
+        let n = 64;
+        let mean_z = |sample: fn(f64, f64) -> Tuple| {
+            grid(n).map(|(u, v)| sample(u, v).z).sum::<f64>() / (n * n) as f64
+        };
+
+        // E[z] is 0 over the sphere, 1/2 uniform and 2/3 cosine-weighted
+        assert!(mean_z(uniform_sphere).abs() < 0.01);
+        assert!((mean_z(uniform_hemisphere) - 0.5).abs() < 0.01);
+        assert!((mean_z(cosine_hemisphere) - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        assert!(equal(uniform_sphere_pdf() * 4.0 * PI, 1.0));
+        assert!(equal(uniform_hemisphere_pdf() * 2.0 * PI, 1.0));
+        assert!(equal(cosine_hemisphere_pdf(1.0), 1.0 / PI));
+        assert_eq!(cosine_hemisphere_pdf(-0.5), 0.0);
+    }
+}
@@ -0,0 +1,198 @@
+#![allow(dead_code)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::canvas::Canvas;
+use crate::color::Color;
+
+const DEFAULT_TILE_SIZE: usize = 16;
+
+/// A rectangular block of pixels rendered as one unit of work.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    fn shade<F>(&self, shade: &F) -> Vec<Color>
+    where
+        F: Fn(usize, usize) -> Color,
+    {
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+
+        for y in self.y..self.y + self.height {
+            for x in self.x..self.x + self.width {
+                pixels.push(shade(x, y));
+            }
+        }
+
+        pixels
+    }
+
+    fn blit(&self, canvas: &mut Canvas, pixels: &[Color]) {
+        for (y, row) in pixels.chunks_exact(self.width).enumerate() {
+            canvas.row_mut(self.y + y)[self.x..self.x + self.width].copy_from_slice(row);
+        }
+    }
+}
+
+/// Splits a `width` x `height` image into row-major tiles of at most
+/// `tile_size` pixels on a side. Edge tiles are clipped to the image.
+pub fn tiles(width: usize, height: usize, tile_size: usize) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let mut tiles = vec![];
+
+    for y in (0..height).step_by(tile_size) {
+        for x in (0..width).step_by(tile_size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            });
+        }
+    }
+
+    tiles
+}
+
+/// Renders every pixel on the calling thread, one row after another.
+pub fn render_serial<F>(width: usize, height: usize, shade: F) -> Canvas
+where
+    F: Fn(usize, usize) -> Color,
+{
+    let mut canvas = Canvas::new(width, height);
+
+    for (x, y, color) in canvas.enumerate_pixels_mut() {
+        *color = shade(x, y);
+    }
+
+    canvas
+}
+
+pub struct Renderer {
+    pub threads: usize,
+    pub tile_size: usize,
+}
+
+impl Renderer {
+    pub fn new(threads: usize, tile_size: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            tile_size: tile_size.max(1),
+        }
+    }
+
+    /// Renders the image by handing tiles out to `threads` workers.
+    ///
+    /// Workers pull the next unclaimed tile from a shared counter, so a slow
+    /// tile doesn't hold up the rest. `shade` is called once per pixel with
+    /// its `(x, y)` coordinate and must not depend on the order of calls; the
+    /// result is then identical to `render_serial`.
+    pub fn render<F>(&self, width: usize, height: usize, shade: F) -> Canvas
+    where
+        F: Fn(usize, usize) -> Color + Sync,
+    {
+        if self.threads == 1 {
+            return render_serial(width, height, shade);
+        }
+
+        let tiles = tiles(width, height, self.tile_size);
+        let next = AtomicUsize::new(0);
+        let mut canvas = Canvas::new(width, height);
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+
+            for _ in 0..self.threads.min(tiles.len()) {
+                let sender = sender.clone();
+                let (tiles, next, shade) = (&tiles, &next, &shade);
+
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(tile) = tiles.get(index) else {
+                        break;
+                    };
+
+                    if sender.send((*tile, tile.shade(shade))).is_err() {
+                        break;
+                    }
+                });
+            }
+
+            drop(sender);
+
+            for (tile, pixels) in receiver {
+                tile.blit(&mut canvas, &pixels);
+            }
+        });
+
+        canvas
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        Self::new(threads, DEFAULT_TILE_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(x: usize, y: usize) -> Color {
+        Color::new(x as f64 / 37.0, y as f64 / 23.0, ((x * y) % 7) as f64 / 7.0)
+    }
+
+    #[test]
+    fn tiles_cover_the_image_without_overlap() {
+        let tiles = tiles(10, 7, 4);
+        let mut covered = vec![0; 10 * 7];
+
+        for tile in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[y * 10 + x] += 1;
+                }
+            }
+        }
+
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x: 8,
+                y: 4,
+                width: 2,
+                height: 3
+            }
+        );
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn parallel_render_matches_serial_render() {
+        let serial = render_serial(37, 23, gradient);
+
+        for (threads, tile_size) in [(1, 8), (2, 1), (4, 5), (8, 64)] {
+            let parallel = Renderer::new(threads, tile_size).render(37, 23, gradient);
+
+            assert_eq!(parallel.data, serial.data);
+        }
+    }
+
+    #[test]
+    fn rendering_an_empty_image() {
+        let canvas = Renderer::new(4, 16).render(0, 0, gradient);
+
+        assert_eq!(canvas.width, 0);
+        assert_eq!(canvas.height, 0);
+    }
+}
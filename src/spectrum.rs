@@ -0,0 +1,563 @@
+#![allow(dead_code)]
+use std::ops::{Add, Mul};
+use std::sync::OnceLock;
+
+use crate::canvas::Canvas;
+use crate::color::space::Xyz;
+use crate::color::Color;
+use crate::random::Pcg32;
+use crate::render::Renderer;
+
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+/// Wavelengths carried together along a path: one hero plus evenly spaced
+/// companions.
+pub const WAVELENGTHS: usize = 4;
+
+const FIT_STEPS: usize = 8;
+const FIT_ITERATIONS: usize = 15;
+/// Spacing of the table used to fit and integrate spectra, in nanometres.
+const TABLE_STEP: f64 = 5.0;
+
+/// A piecewise Gaussian whose width differs either side of the peak.
+fn lobe(lambda: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let sigma = if lambda < mean { below } else { above };
+    (-0.5 * ((lambda - mean) / sigma).powi(2)).exp()
+}
+
+/// The CIE 1931 2° color matching functions at `lambda` nanometres, using
+/// the multi-lobe fit from Wyman, Sloan and Shirley (2013).
+pub fn color_matching(lambda: f64) -> Xyz {
+    Xyz {
+        x: 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7)
+            - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        y: 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        z: 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+/// Integrates `f` over the visible range with the midpoint rule.
+fn integrate<F: Fn(f64) -> f64>(f: F) -> f64 {
+    let steps = ((LAMBDA_MAX - LAMBDA_MIN) / TABLE_STEP) as usize;
+    (0..steps)
+        .map(|i| f(LAMBDA_MIN + (i as f64 + 0.5) * TABLE_STEP) * TABLE_STEP)
+        .sum()
+}
+
+struct Reference {
+    /// `∫ȳ`, so that a flat spectrum of 1.0 has a luminance of 1.0.
+    y_integral: f64,
+    /// The RGB of a flat spectrum, divided out so it comes back white.
+    white: Color,
+    /// White-balanced RGB response at each table wavelength, weighted for
+    /// integration.
+    table: Vec<(f64, Color)>,
+}
+
+fn reference() -> &'static Reference {
+    static REFERENCE: OnceLock<Reference> = OnceLock::new();
+
+    REFERENCE.get_or_init(|| {
+        let y_integral = integrate(|lambda| color_matching(lambda).y);
+        let white = Color::from_xyz(Xyz {
+            x: integrate(|lambda| color_matching(lambda).x) / y_integral,
+            y: 1.0,
+            z: integrate(|lambda| color_matching(lambda).z) / y_integral,
+        });
+
+        let steps = ((LAMBDA_MAX - LAMBDA_MIN) / TABLE_STEP) as usize;
+        let table = (0..steps)
+            .map(|i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * TABLE_STEP;
+                let rgb = xyz_to_balanced_rgb(color_matching(lambda), white);
+                let weight = TABLE_STEP / y_integral;
+                (
+                    lambda,
+                    Color::new(rgb.red * weight, rgb.green * weight, rgb.blue * weight),
+                )
+            })
+            .collect();
+
+        Reference {
+            y_integral,
+            white,
+            table,
+        }
+    })
+}
+
+fn xyz_to_balanced_rgb(xyz: Xyz, white: Color) -> Color {
+    let rgb = Color::from_xyz(xyz);
+    Color::new(
+        rgb.red / white.red,
+        rgb.green / white.green,
+        rgb.blue / white.blue,
+    )
+}
+
+/// Converts a continuous spectrum to linear RGB. The result is white
+/// balanced so that a flat spectrum of 1.0 gives `(1, 1, 1)`.
+pub fn spectrum_to_rgb<F: Fn(f64) -> f64>(spectrum: F) -> Color {
+    reference()
+        .table
+        .iter()
+        .fold(Color::new(0.0, 0.0, 0.0), |sum, &(lambda, weight)| {
+            let value = spectrum(lambda);
+            sum + Color::new(
+                weight.red * value,
+                weight.green * value,
+                weight.blue * value,
+            )
+        })
+}
+
+/// The wavelengths a path carries, with the density each was sampled at.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; WAVELENGTHS],
+    pub pdf: [f64; WAVELENGTHS],
+}
+
+impl SampledWavelengths {
+    /// Hero wavelength sampling: `u` in `0.0..1.0` picks the hero uniformly
+    /// and the others follow at equal spacing, wrapping around the range.
+    pub fn sample_uniform(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut lambda = [0.0; WAVELENGTHS];
+
+        for (i, l) in lambda.iter_mut().enumerate() {
+            let offset = (u + i as f64 / WAVELENGTHS as f64).fract();
+            *l = LAMBDA_MIN + offset * range;
+        }
+
+        Self {
+            lambda,
+            pdf: [1.0 / range; WAVELENGTHS],
+        }
+    }
+
+    /// Drops every wavelength but the hero, e.g. after refraction through a
+    /// dispersive surface sends each wavelength a different way. The hero's
+    /// density is scaled so estimates stay unbiased.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+
+        self.pdf[1..].fill(0.0);
+        self.pdf[0] /= WAVELENGTHS as f64;
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+}
+
+/// Spectral values at the wavelengths of a `SampledWavelengths`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SampledSpectrum(pub [f64; WAVELENGTHS]);
+
+impl SampledSpectrum {
+    pub fn constant(value: f64) -> Self {
+        Self([value; WAVELENGTHS])
+    }
+
+    /// Estimates XYZ from these samples, dividing each by its density.
+    pub fn to_xyz(self, wavelengths: &SampledWavelengths) -> Xyz {
+        let mut xyz = Xyz {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+
+        for i in 0..WAVELENGTHS {
+            if wavelengths.pdf[i] == 0.0 {
+                continue;
+            }
+
+            let matching = color_matching(wavelengths.lambda[i]);
+            let weight = self.0[i] / wavelengths.pdf[i];
+            xyz.x += matching.x * weight;
+            xyz.y += matching.y * weight;
+            xyz.z += matching.z * weight;
+        }
+
+        let scale = WAVELENGTHS as f64 * reference().y_integral;
+        Xyz {
+            x: xyz.x / scale,
+            y: xyz.y / scale,
+            z: xyz.z / scale,
+        }
+    }
+
+    /// Estimates white-balanced linear RGB, matching `spectrum_to_rgb`.
+    pub fn to_rgb(self, wavelengths: &SampledWavelengths) -> Color {
+        xyz_to_balanced_rgb(self.to_xyz(wavelengths), reference().white)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Mul<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self(self.0.map(|v| v * rhs))
+    }
+}
+
+fn sigmoid(x: f64) -> f64 {
+    0.5 + x / (2.0 * (1.0 + x * x).sqrt())
+}
+
+fn sigmoid_derivative(x: f64) -> f64 {
+    0.5 / (1.0 + x * x).powf(1.5)
+}
+
+/// Position of `lambda` in the visible range, mapped to `0.0..=1.0`.
+fn normalized(lambda: f64) -> f64 {
+    (lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN)
+}
+
+/// Solves a 3x3 linear system by Cramer's rule.
+fn solve(a: [[f64; 3]; 3], b: [f64; 3]) -> Option<[f64; 3]> {
+    let det = |m: [[f64; 3]; 3]| {
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    };
+
+    let d = det(a);
+    if d.abs() < 1e-300 {
+        return None;
+    }
+
+    Some(std::array::from_fn(|column| {
+        let mut m = a;
+        for (row, value) in m.iter_mut().zip(b) {
+            row[column] = value;
+        }
+        det(m) / d
+    }))
+}
+
+/// A smooth spectrum reproducing an RGB color: a sigmoid of a quadratic in
+/// wavelength, as proposed by Jakob and Hanika (2019), times a scale.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RgbSpectrum {
+    coefficients: [f64; 3],
+    scale: f64,
+}
+
+impl RgbSpectrum {
+    /// Upsamples a reflectance. Channels are clamped to `0.0..=1.0`, and the
+    /// result never leaves that range at any wavelength.
+    pub fn albedo(color: Color) -> Self {
+        let clamped = Color::new(
+            color.red.clamp(0.0, 1.0),
+            color.green.clamp(0.0, 1.0),
+            color.blue.clamp(0.0, 1.0),
+        );
+
+        if clamped.red == clamped.green && clamped.green == clamped.blue {
+            return Self::gray(clamped.red);
+        }
+
+        Self {
+            coefficients: fit(clamped),
+            scale: 1.0,
+        }
+    }
+
+    /// Upsamples an emitted color, which may be brighter than 1.0. Negative
+    /// channels are treated as 0.
+    pub fn illuminant(color: Color) -> Self {
+        let color = Color::new(
+            color.red.max(0.0),
+            color.green.max(0.0),
+            color.blue.max(0.0),
+        );
+        let max = color.red.max(color.green).max(color.blue);
+
+        if color.red == color.green && color.green == color.blue {
+            return Self::gray(max);
+        }
+
+        // Fitting at half brightness keeps the target away from the
+        // sigmoid's asymptotes.
+        let scale = 2.0 * max;
+        let target = Color::new(color.red / scale, color.green / scale, color.blue / scale);
+        Self {
+            coefficients: fit(target),
+            scale,
+        }
+    }
+
+    /// A flat spectrum: the sigmoid of zero is 0.5 everywhere.
+    fn gray(value: f64) -> Self {
+        Self {
+            coefficients: [0.0; 3],
+            scale: 2.0 * value,
+        }
+    }
+
+    pub fn eval(&self, lambda: f64) -> f64 {
+        let t = normalized(lambda);
+        let [c0, c1, c2] = self.coefficients;
+        self.scale * sigmoid(c0 * t * t + c1 * t + c2)
+    }
+
+    pub fn sample(&self, wavelengths: &SampledWavelengths) -> SampledSpectrum {
+        SampledSpectrum(wavelengths.lambda.map(|lambda| self.eval(lambda)))
+    }
+}
+
+/// Finds sigmoid coefficients whose spectrum integrates to `target`, by
+/// damped Gauss-Newton steps along a path from mid gray, which the zero
+/// coefficients reproduce exactly.
+fn fit(target: Color) -> [f64; 3] {
+    let table = &reference().table;
+    let mut c = [0.0; 3];
+
+    for step in 1..=FIT_STEPS {
+        let blend = step as f64 / FIT_STEPS as f64;
+        let goal = [
+            0.5 + (target.red - 0.5) * blend,
+            0.5 + (target.green - 0.5) * blend,
+            0.5 + (target.blue - 0.5) * blend,
+        ];
+        let mut damping = 1e-3;
+
+        for _ in 0..FIT_ITERATIONS {
+            let mut residual = [0.0; 3];
+            let mut jacobian = [[0.0; 3]; 3];
+
+            let evaluate = |c: [f64; 3]| {
+                let mut rgb = [0.0; 3];
+                for &(lambda, weight) in table {
+                    let t = normalized(lambda);
+                    let s = sigmoid(c[0] * t * t + c[1] * t + c[2]);
+                    rgb[0] += weight.red * s;
+                    rgb[1] += weight.green * s;
+                    rgb[2] += weight.blue * s;
+                }
+                rgb
+            };
+
+            for &(lambda, weight) in table {
+                let t = normalized(lambda);
+                let x = c[0] * t * t + c[1] * t + c[2];
+                let s = sigmoid(x);
+                let ds = sigmoid_derivative(x);
+                let weights = [weight.red, weight.green, weight.blue];
+
+                for channel in 0..3 {
+                    residual[channel] += weights[channel] * s;
+                    let d = weights[channel] * ds;
+                    jacobian[channel][0] += d * t * t;
+                    jacobian[channel][1] += d * t;
+                    jacobian[channel][2] += d;
+                }
+            }
+            for channel in 0..3 {
+                residual[channel] -= goal[channel];
+            }
+
+            let error = residual.iter().map(|r| r * r).sum::<f64>();
+            if error < 1e-14 {
+                break;
+            }
+
+            // Levenberg-Marquardt: (JᵀJ + λI) δ = -Jᵀr
+            let mut normal = [[0.0; 3]; 3];
+            let mut gradient = [0.0; 3];
+            for i in 0..3 {
+                for j in 0..3 {
+                    normal[i][j] = (0..3).map(|k| jacobian[k][i] * jacobian[k][j]).sum();
+                }
+                normal[i][i] += damping;
+                gradient[i] = -(0..3).map(|k| jacobian[k][i] * residual[k]).sum::<f64>();
+            }
+
+            let Some(delta) = solve(normal, gradient) else {
+                break;
+            };
+            let candidate = [c[0] + delta[0], c[1] + delta[1], c[2] + delta[2]];
+            let rgb = evaluate(candidate);
+            let candidate_error = (0..3).map(|k| (rgb[k] - goal[k]).powi(2)).sum::<f64>();
+
+            if candidate_error < error {
+                c = candidate;
+                damping = (damping * 0.1).max(1e-12);
+            } else {
+                damping *= 10.0;
+            }
+        }
+    }
+
+    c
+}
+
+impl Renderer {
+    /// Renders in spectral mode: `shade` returns the radiance arriving at a
+    /// pixel for a set of wavelengths, and each pixel averages `samples`
+    /// stratified sets through the color matching functions into linear
+    /// RGB. The same `seed` reproduces the same wavelength noise.
+    pub fn render_spectral<F>(
+        &self,
+        width: usize,
+        height: usize,
+        samples: usize,
+        seed: u64,
+        shade: F,
+    ) -> Canvas
+    where
+        F: Fn(usize, usize, &SampledWavelengths) -> SampledSpectrum + Sync,
+    {
+        let samples = samples.max(1);
+
+        self.render(width, height, |x, y| {
+            // decorrelates the wavelengths neighbouring pixels start from
+            let offset = Pcg32::for_pixel(seed, x, y).next_f64();
+            let mut sum = Color::new(0.0, 0.0, 0.0);
+
+            for sample in 0..samples {
+                let u = ((sample as f64 + offset) / samples as f64).fract();
+                let wavelengths = SampledWavelengths::sample_uniform(u);
+                sum = sum + shade(x, y, &wavelengths).to_rgb(&wavelengths);
+            }
+
+            let n = samples as f64;
+            Color::new(sum.red / n, sum.green / n, sum.blue / n)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Color, b: Color, tolerance: f64) {
+        let error = (a.red - b.red)
+            .abs()
+            .max((a.green - b.green).abs())
+            .max((a.blue - b.blue).abs());
+        assert!(error < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn matching_functions_peak_where_expected() {
+        assert!((color_matching(555.0).y - 1.0).abs() < 0.01);
+        assert!((color_matching(600.0).x - 1.06).abs() < 0.01);
+        assert!((color_matching(445.0).z - 1.78).abs() < 0.02);
+        assert!((reference().y_integral - 106.86).abs() < 0.1);
+    }
+
+    #[test]
+    fn a_flat_spectrum_is_white() {
+        assert_close(spectrum_to_rgb(|_| 1.0), Color::new(1.0, 1.0, 1.0), 1e-9);
+        assert_close(
+            spectrum_to_rgb(|_| 0.25),
+            Color::new(0.25, 0.25, 0.25),
+            1e-9,
+        );
+    }
+
+    #[test]
+    fn sampling_hero_wavelengths() {
+        let wavelengths = SampledWavelengths::sample_uniform(0.5);
+        assert_eq!(wavelengths.lambda, [595.0, 712.5, 360.0, 477.5]);
+
+        let mut terminated = wavelengths;
+        terminated.terminate_secondary();
+        assert!(terminated.secondary_terminated());
+        assert_eq!(terminated.pdf[0], wavelengths.pdf[0] / 4.0);
+
+        // the hero alone still estimates a flat spectrum without bias
+        let n = 2000;
+        let mut sum = Color::new(0.0, 0.0, 0.0);
+        for i in 0..n {
+            let mut wavelengths = SampledWavelengths::sample_uniform((i as f64 + 0.5) / n as f64);
+            wavelengths.terminate_secondary();
+            sum = sum + SampledSpectrum::constant(1.0).to_rgb(&wavelengths);
+        }
+        let mean = Color::new(
+            sum.red / n as f64,
+            sum.green / n as f64,
+            sum.blue / n as f64,
+        );
+        assert_close(mean, Color::new(1.0, 1.0, 1.0), 0.01);
+    }
+
+    #[test]
+    fn upsampling_albedo() {
+        let colors = [
+            Color::new(0.2, 0.5, 0.8),
+            Color::new(0.8, 0.3, 0.1),
+            Color::new(0.05, 0.6, 0.3),
+            Color::new(0.5, 0.5, 0.5),
+            Color::new(1.0, 1.0, 1.0),
+        ];
+
+        for color in colors {
+            let spectrum = RgbSpectrum::albedo(color);
+            assert_close(spectrum_to_rgb(|l| spectrum.eval(l)), color, 0.005);
+
+            for lambda in [360.0, 450.0, 550.0, 650.0, 830.0] {
+                assert!((0.0..=1.0).contains(&spectrum.eval(lambda)));
+            }
+        }
+    }
+
+    #[test]
+    fn upsampling_illuminants() {
+        for color in [Color::new(4.0, 2.0, 1.0), Color::new(0.0, 0.0, 0.0)] {
+            let spectrum = RgbSpectrum::illuminant(color);
+            assert_close(spectrum_to_rgb(|l| spectrum.eval(l)), color, 0.02);
+        }
+    }
+
+    #[test]
+    fn rendering_spectrally() {
+        let light = RgbSpectrum::illuminant(Color::new(1.0, 0.8, 0.6));
+        let albedo = RgbSpectrum::albedo(Color::new(0.2, 0.5, 0.8));
+
+        let canvas = Renderer::new(2, 4).render_spectral(3, 2, 64, 0, |x, _, wavelengths| {
+            if x == 0 {
+                light.sample(wavelengths)
+            } else {
+                light.sample(wavelengths) * albedo.sample(wavelengths)
+            }
+        });
+
+        assert_close(canvas.pixel_at(0, 1), Color::new(1.0, 0.8, 0.6), 0.02);
+        // a spectral product is only roughly the RGB product
+        assert_close(canvas.pixel_at(2, 0), Color::new(0.2, 0.4, 0.48), 0.05);
+    }
+
+    #[test]
+    fn seeding_spectral_noise() {
+        let light = RgbSpectrum::illuminant(Color::new(1.0, 0.8, 0.6));
+        let render = |seed| {
+            Renderer::new(2, 4)
+                .render_spectral(4, 4, 2, seed, |_, _, wavelengths| light.sample(wavelengths))
+        };
+
+        assert_eq!(render(7), render(7));
+        assert_ne!(render(7), render(8));
+    }
+}
@@ -2,6 +2,7 @@
 
 use std::ops::{Add, Div, Mul, Sub};
 
+#[cfg(test)]
 use crate::utils::equal;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -45,7 +46,7 @@ impl Tuple {
         self.w = -self.w;
     }
 
-    fn magnitude(&self) -> f64 {
+    pub fn magnitude(&self) -> f64 {
         (self.x.powi(2) + self.y.powi(2) + self.z.powi(2) + self.w.powi(2)).sqrt()
     }
 
@@ -58,11 +59,11 @@ impl Tuple {
         }
     }
 
-    fn dot(&self, other: &Self) -> f64 {
+    pub fn dot(&self, other: &Self) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w
     }
 
-    fn cross(&self, other: &Self) -> Self {
+    pub fn cross(&self, other: &Self) -> Self {
         Self::to_vector(
             self.y * other.z - self.z * other.y,
             self.z * other.x - self.x * other.z,
@@ -172,7 +173,7 @@ fn to_vector_create_tuple() {
     assert_eq!(a.x, 4.0);
     assert_eq!(a.y, -4.0);
     assert_eq!(a.z, 3.0);
-    assert_eq!(a.is_point(), false);
+    assert!(!a.is_point());
 }
 
 #[test]
@@ -1,3 +1,4 @@
+#![allow(dead_code)]
 const EPSILON: f64 = 0.0005;
 
 pub fn equal(a: f64, b: f64) -> bool {